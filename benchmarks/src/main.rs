@@ -7,6 +7,7 @@ use std::time::{Duration, Instant};
 use chrono::Local;
 use cli_table::{print_stdout, Style, Table};
 use colored::*;
+use fig2img::commands::convert::ConvertOptions;
 use fig2img::commands::download::DownloadOptions;
 use fig2img::commands::{self};
 
@@ -114,6 +115,7 @@ async fn run_benchmark(
     downloads_path.clone(),
     output_webp_path.clone(),
     "webp".to_string(),
+    ConvertOptions::new(),
   )
   .await;
   let convert_to_webp_duration = start.elapsed();
@@ -123,6 +125,7 @@ async fn run_benchmark(
     downloads_path.clone(),
     output_avif_path.clone(),
     "avif".to_string(),
+    ConvertOptions::new(),
   )
   .await;
   let convert_to_avif_duration = start.elapsed();
@@ -1,6 +1,16 @@
 use std::path::PathBuf;
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
+use fig2img::commands::convert;
+use fig2img::commands::output::OutputFormat;
+use fig2img::core::converter::{
+  AvifAlphaMode, AvifColorModel, ChromaSubsampling, Color, EncoderOptions, WebPBackend,
+};
+use fig2img::core::inputs::{InputOptions, SymlinkPolicy};
+use fig2img::core::manifest::ManifestFormat;
+use fig2img::core::resize::{Fit, ResizeFilter, ResizeOptions};
+use fig2img::core::svg::SvgOptions;
+use fig2img::core::{downloader, extractor};
 
 #[derive(Parser)]
 #[command(name = "fig2img")]
@@ -8,6 +18,18 @@ use clap::{Parser, Subcommand};
 #[command(version = env!("CARGO_PKG_VERSION"))]
 #[command(about = "Downloads images from figma and Convert them to another format", long_about = None)]
 pub struct Cli {
+  /// Output style: text, json (one document at the end) or ndjson (one event per line)
+  #[arg(long, global = true, default_value_t = OutputFormat::Text)]
+  pub output_format: OutputFormat,
+  /// Log more details: -v for progress and timings, -vv for debugging
+  #[arg(long, short = 'v', global = true, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+  pub verbose: u8,
+  /// Only print errors
+  #[arg(long, short = 'q', global = true)]
+  pub quiet: bool,
+  /// Write logs to this file instead of stderr
+  #[arg(long, global = true)]
+  pub log_file: Option<PathBuf>,
   #[command(subcommand)]
   pub command: Commands,
 }
@@ -17,13 +39,383 @@ pub enum Commands {
   Download {
     #[arg(long)]
     output: PathBuf,
+    /// Append a content hash to output file names and write a hash manifest
+    #[arg(long)]
+    content_hash: bool,
+    /// Write an asset manifest describing every exported image (json, toml or yaml)
+    #[arg(long, num_args = 0..=1, default_missing_value = "json")]
+    manifest: Option<ManifestFormat>,
+    /// Scale at which Figma renders the images, from 0.01 to 4
+    #[arg(long, default_value_t = 1.0, value_parser = parse_scale)]
+    scale: f32,
+    /// Lower scales to downsample locally from the --scale render instead of rendering them in
+    /// Figma, separated by commas (e.g. --scale 3 --derive-scales 1,2)
+    #[arg(long, value_delimiter = ',')]
+    derive_scales: Vec<f32>,
+    /// Sharpening applied to derived scales, from 0 (none) to 1
+    #[arg(long, default_value_t = 0.0, value_parser = parse_sharpen)]
+    sharpen: f32,
+    /// Seconds to wait for a connection to the image host
+    #[arg(long, default_value_t = downloader::DEFAULT_CONNECT_TIMEOUT.as_secs())]
+    connect_timeout: u64,
+    /// Seconds to wait for data before a download attempt times out
+    #[arg(long, default_value_t = downloader::DEFAULT_READ_TIMEOUT.as_secs())]
+    read_timeout: u64,
+    /// Seconds to wait for each Figma API response, including rendering the images
+    #[arg(long, default_value_t = extractor::DEFAULT_API_TIMEOUT.as_secs())]
+    api_timeout: u64,
+    /// Number of retries for timeouts, connection errors and 5xx responses; the wait between
+    /// attempts doubles each time, up to a minute
+    #[arg(long, default_value_t = downloader::DEFAULT_MAX_RETRIES)]
+    retries: u32,
+    /// Maximum number of concurrent downloads
+    #[arg(long, short = 'j', default_value_t = downloader::DEFAULT_JOBS)]
+    jobs: usize,
+    /// Maximum number of concurrent downloads against a single host
+    #[arg(long)]
+    max_per_host: Option<usize>,
   },
   Convert {
     #[arg(long)]
     input: PathBuf,
     #[arg(long)]
     output: PathBuf,
+    /// Output format, or several separated by commas (e.g. webp,avif) to decode each input once
     #[arg(long, default_value = "webp")]
     format: String,
+    /// Append a content hash to output file names and write a hash manifest
+    #[arg(long)]
+    content_hash: bool,
+    /// Write an asset manifest describing every converted image (json, toml or yaml)
+    #[arg(long, num_args = 0..=1, default_missing_value = "json")]
+    manifest: Option<ManifestFormat>,
+    #[command(flatten)]
+    inputs: InputArgs,
+    #[command(flatten)]
+    encoder: Box<EncoderArgs>,
+    #[command(flatten)]
+    resize: ResizeArgs,
+    #[command(flatten)]
+    svg: SvgArgs,
+    /// Maximum number of images encoded at once
+    #[arg(
+      long,
+      short = 'j',
+      default_value_t = convert::default_jobs(),
+      value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    jobs: usize,
+    /// Value for a placeholder of a custom converter from the config file, as KEY=VALUE
+    #[arg(long = "option", value_parser = parse_key_value)]
+    options: Vec<(String, String)>,
   },
 }
+
+/// Which files in the input directory are converted.
+#[derive(Args)]
+pub struct InputArgs {
+  /// Also convert images in subdirectories, mirroring them under the output directory
+  #[arg(long, short = 'r')]
+  recursive: bool,
+  /// Only convert files matching this glob (e.g. 'icons/**'); a glob without / matches file names
+  #[arg(long)]
+  include: Vec<String>,
+  /// Leave out files and directories matching this glob (e.g. drafts)
+  #[arg(long)]
+  exclude: Vec<String>,
+  /// Symbolic links: follow, or skip to leave them out
+  #[arg(long, default_value_t)]
+  symlinks: SymlinkPolicy,
+}
+
+impl From<InputArgs> for InputOptions {
+  fn from(args: InputArgs) -> Self {
+    InputOptions::new()
+      .recursive(args.recursive)
+      .include(args.include)
+      .exclude(args.exclude)
+      .symlinks(args.symlinks)
+  }
+}
+
+/// Sizes to write each input at, one output per size.
+#[derive(Args)]
+pub struct ResizeArgs {
+  /// Widths to resize to, separated by commas (e.g. 320,640,1280); the size goes in the file name
+  #[arg(long = "width", value_delimiter = ',', value_parser = clap::value_parser!(u32).range(1..))]
+  widths: Vec<u32>,
+  /// Heights to resize to, separated by commas; paired with --width by position
+  #[arg(long = "height", value_delimiter = ',', value_parser = clap::value_parser!(u32).range(1..))]
+  heights: Vec<u32>,
+  /// Scale down anything whose longer side exceeds this many pixels
+  #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+  max_dimension: Option<u32>,
+  /// How to fit into a --width and --height box: contain, cover (crops) or exact (stretches)
+  #[arg(long, default_value_t)]
+  fit: Fit,
+  /// Resampling filter: lanczos3, or catmull-rom for less ringing around hard edges
+  #[arg(long, default_value_t)]
+  filter: ResizeFilter,
+  /// Also write sizes larger than the input instead of skipping them
+  #[arg(long)]
+  upscale: bool,
+}
+
+impl From<ResizeArgs> for ResizeOptions {
+  fn from(args: ResizeArgs) -> Self {
+    ResizeOptions::new()
+      .widths(args.widths)
+      .heights(args.heights)
+      .max_dimension(args.max_dimension)
+      .fit(args.fit)
+      .filter(args.filter)
+      .upscale(args.upscale)
+  }
+}
+
+/// How SVG inputs are rasterized before encoding.
+#[derive(Args)]
+pub struct SvgArgs {
+  /// Width to rasterize SVG inputs at; with --svg-height, fits inside both keeping the aspect ratio
+  #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+  svg_width: Option<u32>,
+  /// Height to rasterize SVG inputs at
+  #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+  svg_height: Option<u32>,
+  /// Multiplies the size SVG inputs declare, when no --svg-width or --svg-height is given
+  #[arg(long, default_value_t = 1.0)]
+  svg_scale: f32,
+  /// Directory of fonts for SVG text, searched before the system fonts; may be repeated
+  #[arg(long = "font-dir")]
+  font_dirs: Vec<PathBuf>,
+  /// Only use fonts from --font-dir, for output that doesn't depend on the machine
+  #[arg(long)]
+  no_system_fonts: bool,
+}
+
+impl From<SvgArgs> for SvgOptions {
+  fn from(args: SvgArgs) -> Self {
+    SvgOptions::new()
+      .width(args.svg_width)
+      .height(args.svg_height)
+      .scale(args.svg_scale)
+      .font_dirs(args.font_dirs)
+      .system_fonts(!args.no_system_fonts)
+  }
+}
+
+/// Encoder settings. Each one overrides the `[webp]`/`[avif]`/`[jpeg]`/`[jxl]`/`[png]` sections of
+/// the config file.
+#[derive(Args)]
+pub struct EncoderArgs {
+  /// Quality from 0 (smallest) to 100 (best), for WebP, AVIF and JPEG
+  #[arg(long, value_parser = parse_quality)]
+  quality: Option<f32>,
+  /// Quality of the alpha channel from 0 to 100, for WebP and AVIF
+  #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+  alpha_quality: Option<u8>,
+  /// AVIF encoding speed from 1 (smallest files) to 10 (fastest)
+  #[arg(long, value_parser = clap::value_parser!(u8).range(1..=10))]
+  speed: Option<u8>,
+  /// AVIF bits per channel: 8, or 10 to avoid banding and keep 16-bit sources precise
+  #[arg(long, value_parser = parse_bit_depth)]
+  bit_depth: Option<u8>,
+  /// AVIF color model: ycbcr, or rgb to store channels without conversion
+  #[arg(long)]
+  color_model: Option<AvifColorModel>,
+  /// AVIF handling of transparent pixels' color: clean, dirty or premultiplied
+  #[arg(long)]
+  alpha_mode: Option<AvifAlphaMode>,
+  /// WebP compression effort from 0 (fastest) to 6 (smallest files)
+  #[arg(long, value_parser = clap::value_parser!(u8).range(0..=6))]
+  method: Option<u8>,
+  /// Encode WebP and JPEG XL losslessly; --lossless=false turns it off when the config file sets it
+  #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+  lossless: Option<bool>,
+  /// Encode WebP near-losslessly; 100 keeps pixels untouched, 0 adjusts them the most
+  #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+  near_lossless: Option<u8>,
+  /// Use the slower, sharper RGB to YUV conversion for lossy WebP (--sharp-yuv=false to turn off)
+  #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+  sharp_yuv: Option<bool>,
+  /// WebP encoder: native (built in) or cwebp (external command)
+  #[arg(long)]
+  webp_backend: Option<WebPBackend>,
+  /// Write baseline instead of progressive JPEGs (--baseline=false for progressive)
+  #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+  baseline: Option<bool>,
+  /// JPEG chroma subsampling: 420, 422, or 444 for full color resolution
+  #[arg(long)]
+  chroma_subsampling: Option<ChromaSubsampling>,
+  /// Skip mozjpeg's trellis quantization for faster, larger JPEGs (--no-trellis=false to keep it)
+  #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+  no_trellis: Option<bool>,
+  /// Color that transparent pixels are blended onto for JPEG, as #rrggbb
+  #[arg(long)]
+  background: Option<Color>,
+  /// JPEG XL distance from the original: 0 is lossless, 1 visually lossless, up to 25
+  #[arg(long, value_parser = parse_distance)]
+  distance: Option<f32>,
+  /// JPEG XL encoding effort from 1 (fastest) to 10 (smallest files)
+  #[arg(long, value_parser = clap::value_parser!(u8).range(1..=10))]
+  effort: Option<u8>,
+  /// PNG optimization level from 0 (fastest) to 6 (smallest files)
+  #[arg(long, value_parser = clap::value_parser!(u8).range(0..=6))]
+  level: Option<u8>,
+  /// Keep PNG metadata such as text and timestamps (--keep-metadata=false to strip it)
+  #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+  keep_metadata: Option<bool>,
+  /// Reduce PNGs to a palette of at most this many colors (2-256). Lossy
+  #[arg(long, value_parser = clap::value_parser!(u16).range(2..=256))]
+  palette: Option<u16>,
+  /// Dithering strength for --palette from 0 (none) to 1
+  #[arg(long, value_parser = parse_dithering)]
+  dithering: Option<f32>,
+  /// Keep all colors when the --palette image would be below this quality (0-100)
+  #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+  min_quality: Option<u8>,
+  /// Threads each image is encoded with, for AVIF, WebP and JPEG XL (default: CPUs / --jobs)
+  #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+  threads: Option<usize>,
+}
+
+impl EncoderArgs {
+  /// Applies the flags that were given on top of `options`.
+  pub fn apply(self, options: EncoderOptions) -> EncoderOptions {
+    let mut webp = options.webp_options();
+    let mut avif = options.avif_options();
+    let mut jpeg = options.jpeg_options();
+    let mut jxl = options.jxl_options();
+    let mut png = options.png_options();
+
+    if let Some(quality) = self.quality {
+      webp = webp.quality(quality);
+      avif = avif.quality(quality);
+      jpeg = jpeg.quality(quality);
+    }
+    if let Some(alpha_quality) = self.alpha_quality {
+      webp = webp.alpha_quality(alpha_quality);
+      avif = avif.alpha_quality(alpha_quality.into());
+    }
+    if let Some(speed) = self.speed {
+      avif = avif.speed(speed);
+    }
+    if let Some(bit_depth) = self.bit_depth {
+      avif = avif.bit_depth(bit_depth);
+    }
+    if let Some(color_model) = self.color_model {
+      avif = avif.color_model(color_model);
+    }
+    if let Some(alpha_mode) = self.alpha_mode {
+      avif = avif.alpha_mode(alpha_mode);
+    }
+    if let Some(method) = self.method {
+      webp = webp.method(method);
+    }
+    if let Some(lossless) = self.lossless {
+      webp = webp.lossless(lossless);
+      jxl = jxl.lossless(lossless);
+    }
+    if self.near_lossless.is_some() {
+      webp = webp.near_lossless(self.near_lossless);
+    }
+    if let Some(sharp_yuv) = self.sharp_yuv {
+      webp = webp.sharp_yuv(sharp_yuv);
+    }
+    if let Some(backend) = self.webp_backend {
+      webp = webp.backend(backend);
+    }
+    if let Some(baseline) = self.baseline {
+      jpeg = jpeg.progressive(!baseline);
+    }
+    if let Some(chroma_subsampling) = self.chroma_subsampling {
+      jpeg = jpeg.chroma_subsampling(chroma_subsampling);
+    }
+    if let Some(no_trellis) = self.no_trellis {
+      jpeg = jpeg.trellis(!no_trellis);
+    }
+    if let Some(background) = self.background {
+      jpeg = jpeg.background(background);
+    }
+
+    if let Some(distance) = self.distance {
+      jxl = jxl.distance(distance);
+    }
+    if let Some(effort) = self.effort {
+      jxl = jxl.effort(effort);
+    }
+
+    if let Some(level) = self.level {
+      png = png.level(level);
+    }
+    if let Some(keep_metadata) = self.keep_metadata {
+      png = png.strip(!keep_metadata);
+    }
+    if self.palette.is_some() {
+      png = png.palette(self.palette);
+    }
+    if let Some(dithering) = self.dithering {
+      png = png.dithering(dithering);
+    }
+    if let Some(min_quality) = self.min_quality {
+      png = png.min_quality(min_quality);
+    }
+
+    let options = match self.threads {
+      Some(threads) => options.threads(Some(threads)),
+      None => options,
+    };
+    options.webp(webp).avif(avif).jpeg(jpeg).jxl(jxl).png(png)
+  }
+}
+
+fn parse_quality(value: &str) -> Result<f32, String> {
+  match value.parse::<f32>() {
+    Ok(quality) if (0.0..=100.0).contains(&quality) => Ok(quality),
+    _ => Err(format!("{} is not a number from 0 to 100", value)),
+  }
+}
+
+fn parse_distance(value: &str) -> Result<f32, String> {
+  match value.parse::<f32>() {
+    Ok(distance) if (0.0..=25.0).contains(&distance) => Ok(distance),
+    _ => Err(format!("{} is not a number from 0 to 25", value)),
+  }
+}
+
+fn parse_dithering(value: &str) -> Result<f32, String> {
+  match value.parse::<f32>() {
+    Ok(dithering) if (0.0..=1.0).contains(&dithering) => Ok(dithering),
+    _ => Err(format!("{} is not a number from 0 to 1", value)),
+  }
+}
+
+/// Figma renders images at 0.01x to 4x.
+fn parse_scale(value: &str) -> Result<f32, String> {
+  match value.parse::<f32>() {
+    Ok(scale) if (0.01..=4.0).contains(&scale) => Ok(scale),
+    _ => Err(format!("{} is not a number from 0.01 to 4", value)),
+  }
+}
+
+fn parse_sharpen(value: &str) -> Result<f32, String> {
+  match value.parse::<f32>() {
+    Ok(sharpen) if (0.0..=1.0).contains(&sharpen) => Ok(sharpen),
+    _ => Err(format!("{} is not a number from 0 to 1", value)),
+  }
+}
+
+fn parse_bit_depth(value: &str) -> Result<u8, String> {
+  match value {
+    "8" => Ok(8),
+    "10" => Ok(10),
+    _ => Err(format!("{} is not a supported bit depth (8 or 10)", value)),
+  }
+}
+
+fn parse_key_value(value: &str) -> Result<(String, String), String> {
+  match value.split_once('=') {
+    Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+    _ => Err(format!("{} is not in KEY=VALUE form", value)),
+  }
+}
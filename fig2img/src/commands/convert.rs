@@ -1,62 +1,1062 @@
-use std::path::PathBuf;
+use std::cmp::Ordering;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use futures::future;
+use indicatif::HumanBytes;
+use serde::{Deserialize, Serialize};
 use tokio::fs;
 use tokio::sync::Semaphore;
 
-use crate::core::converter::ImageConverter;
+use crate::commands::progress::{Phase, Progress};
+use crate::commands::report::RunReport;
+use crate::core::converter::{Converter, ConverterRegistry, EncoderOptions, SourceImage};
+use crate::core::inputs::{InputOptions, Inputs};
+use crate::core::manifest::{AssetEntry, AssetManifest, ManifestFormat, MANIFEST_FILENAME};
+use crate::core::resize::ResizeOptions;
+use crate::core::svg::{self, SvgOptions, SvgRasterizer};
+use crate::utils::hash::{self, HashManifest, HASH_MANIFEST_FILENAME};
 
-pub async fn execute(input_dir: PathBuf, output_dir: PathBuf, format: String) {
-  if format != "webp" && format != "avif" {
-    eprintln!("[❌] Unsupported format: {}", format);
-    return;
+#[derive(Deserialize, Serialize)]
+pub struct ConvertOptions {
+  #[serde(default)]
+  quiet: bool,
+  #[serde(default)]
+  content_hash: bool,
+  #[serde(default)]
+  manifest: Option<ManifestFormat>,
+  #[serde(default)]
+  encoder: EncoderOptions,
+  #[serde(default)]
+  resize: ResizeOptions,
+  #[serde(default)]
+  inputs: InputOptions,
+  #[serde(default)]
+  svg: SvgOptions,
+  #[serde(default = "default_jobs")]
+  jobs: usize,
+  /// Overrides the built-in converters, which are otherwise configured from `encoder`.
+  #[serde(skip)]
+  converters: Option<ConverterRegistry>,
+  #[serde(skip)]
+  progress: Progress,
+}
+
+/// Images encoded at once by default: one per CPU.
+pub fn default_jobs() -> usize {
+  std::thread::available_parallelism().map_or(1, |cpus| cpus.get())
+}
+
+/// Threads each image may be encoded with so that `jobs` encodes running at once use about one
+/// thread per CPU.
+pub fn threads_per_image(jobs: usize) -> usize {
+  (default_jobs() / jobs.max(1)).max(1)
+}
+
+impl Default for ConvertOptions {
+  fn default() -> Self {
+    Self {
+      quiet: false,
+      content_hash: false,
+      manifest: None,
+      encoder: EncoderOptions::default(),
+      resize: ResizeOptions::default(),
+      inputs: InputOptions::default(),
+      svg: SvgOptions::default(),
+      jobs: default_jobs(),
+      converters: None,
+      progress: Progress::default(),
+    }
+  }
+}
+
+impl ConvertOptions {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn quiet(mut self, quiet: bool) -> Self {
+    self.quiet = quiet;
+    self
   }
 
+  pub fn content_hash(mut self, content_hash: bool) -> Self {
+    self.content_hash = content_hash;
+    self
+  }
+
+  pub fn manifest(mut self, manifest: Option<ManifestFormat>) -> Self {
+    self.manifest = manifest;
+    self
+  }
+
+  pub fn encoder(mut self, encoder: EncoderOptions) -> Self {
+    self.encoder = encoder;
+    self
+  }
+
+  pub fn resize(mut self, resize: ResizeOptions) -> Self {
+    self.resize = resize;
+    self
+  }
+
+  pub fn inputs(mut self, inputs: InputOptions) -> Self {
+    self.inputs = inputs;
+    self
+  }
+
+  pub fn svg(mut self, svg: SvgOptions) -> Self {
+    self.svg = svg;
+    self
+  }
+
+  /// Maximum number of images encoded at once.
+  pub fn jobs(mut self, jobs: usize) -> Self {
+    self.jobs = jobs;
+    self
+  }
+
+  pub fn converters(mut self, converters: ConverterRegistry) -> Self {
+    self.converters = Some(converters);
+    self
+  }
+
+  pub fn progress(mut self, progress: Progress) -> Self {
+    self.progress = progress;
+    self
+  }
+}
+
+pub async fn execute(
+  input_dir: PathBuf,
+  output_dir: PathBuf,
+  format: String,
+  options: ConvertOptions,
+) -> RunReport {
+  let mut report = RunReport::new();
+  let quiet = options.quiet;
+
+  let converters = match &options.converters {
+    Some(converters) => converters.clone(),
+    None => {
+      let threads = options
+        .encoder
+        .threads_per_image()
+        .unwrap_or_else(|| threads_per_image(options.jobs));
+      ConverterRegistry::with_defaults(&options.encoder.threads(Some(threads)))
+    }
+  };
+  let selected = match select_converters(&converters, &format) {
+    Ok(selected) => selected,
+    Err(e) => {
+      tracing::error!("{}", e);
+      report.failed(&format, e);
+      return report;
+    }
+  };
+  if let Err(e) = options.inputs.validate() {
+    tracing::error!("Invalid input filters: {}", e);
+    report.failed("input filters", e);
+    return report;
+  }
+  if let Err(e) = options.resize.validate() {
+    tracing::error!("Invalid resize options: {}", e);
+    report.failed("resize options", e);
+    return report;
+  }
+  if let Err(e) = options.svg.validate() {
+    tracing::error!("Invalid SVG options: {}", e);
+    report.failed("svg options", e);
+    return report;
+  }
+  for converter in &selected {
+    if let Err(e) = converter.validate() {
+      tracing::error!("Invalid encoder options: {}", e);
+      report.failed("encoder options", e);
+      return report;
+    }
+  }
+  let converters = Arc::new(selected);
+
   if let Err(e) = fs::create_dir_all(&output_dir).await {
-    eprintln!("[❌] Failed to create output directory: {}", e);
-    return;
+    tracing::error!("Failed to create output directory: {}", e);
+    report.failed(output_dir.display(), e);
+    return report;
   }
 
-  match fs::read_dir(&input_dir).await {
-    Ok(mut entries) => {
-      let mut conversion_tasks = Vec::new();
-      let semaphore = Arc::new(Semaphore::new(4));
+  let inputs = match read_inputs(&input_dir, &output_dir, &options.inputs).await {
+    Ok(inputs) => {
+      for (path, reason) in inputs.skipped {
+        report.skipped(path.display(), reason);
+      }
+      drop_same_names(inputs.files, &mut report)
+    }
+    Err(e) => {
+      tracing::error!("Failed to read input directory: {}", e);
+      report.failed(input_dir.display(), e);
+      return report;
+    }
+  };
+
+  let svg = match load_svg_rasterizer(&inputs, &options.svg).await {
+    Ok(svg) => svg,
+    Err(e) => {
+      tracing::error!("Failed to set up SVG rasterization: {}", e);
+      report.failed("svg rasterizer", e);
+      return report;
+    }
+  };
+
+  let progress = options.progress.clone();
+  let resize = Arc::new(options.resize.clone());
+  progress.phase_started(
+    Phase::Convert,
+    Some((inputs.len() * converters.len() * resize.variant_count()) as u64),
+  );
+
+  let mut conversion_tasks = Vec::new();
+  let semaphore = Arc::new(Semaphore::new(options.jobs.max(1)));
+  let source_manifest = match options.manifest {
+    Some(_) => AssetManifest::read(&input_dir).await,
+    None => None,
+  };
+  // Name each failure after its format when there are several.
+  let several_formats = converters.len() > 1;
+
+  for path in inputs {
+    let input_path = path.to_str().unwrap().to_string();
+    // Mirror the input's subdirectory under the output directory.
+    let output_dir = match path.strip_prefix(&input_dir).ok().and_then(Path::parent) {
+      Some(relative_dir) => output_dir.join(relative_dir),
+      None => output_dir.clone(),
+    };
+    let content_hash = options.content_hash;
+    let manifest = options.manifest;
+    let converters = Arc::clone(&converters);
+    let resize = Arc::clone(&resize);
+    let svg = svg.clone();
+    // The Figma metadata recorded when the input was downloaded, if any.
+    let source = source_manifest
+      .as_ref()
+      .and_then(|manifest| manifest.find_by_output(&input_dir, &path))
+      .cloned();
+    let progress = progress.clone();
 
-      while let Some(entry) = entries.next_entry().await.unwrap() {
-        let path = entry.path();
+    let semaphore = Arc::clone(&semaphore);
 
-        if path.extension().map_or(false, |ext| ext == "png") {
-          let file_stem = path.file_stem().unwrap().to_str().unwrap().to_string();
-          let output_path = output_dir.join(format!("{}.{}", &file_stem, format));
+    let task = tokio::spawn(async move {
+      // Held until the task ends, so at most `jobs` inputs are decoded and encoded at once.
+      let _permit = semaphore.acquire().await.unwrap();
+      for _ in 0..converters.len() * resize.variant_count() {
+        progress.task_started(Phase::Convert, &input_path);
+      }
+      // Read before converting, since an output may replace the input.
+      let input_bytes = fs::metadata(&input_path).await.map_or(0, |m| m.len());
+
+      let encoded = match fs::create_dir_all(&output_dir).await {
+        Ok(()) => {
+          let input_path = input_path.clone();
+          let converters = Arc::clone(&converters);
+          tokio::task::spawn_blocking(move || {
+            let svg = svg.as_deref();
+            encode_all(
+              Path::new(&input_path),
+              &output_dir,
+              &converters,
+              &resize,
+              svg,
+            )
+          })
+          .await
+          .map_err(Error::other)
+        }
+        Err(e) => Err(e),
+      };
+      let (encoded, skipped) = match encoded {
+        Ok(encoded) => encoded,
+        Err(e) => {
+          progress.task_failed(Phase::Convert, &input_path, &e);
+          if !quiet {
+            eprintln!("[❌] Failed conversion: {}", e);
+          }
+          return (vec![Err(e)], Vec::new(), input_bytes);
+        }
+      };
 
-          let input_path = path.to_str().unwrap().to_string();
-          let output_path = output_path.to_str().unwrap().to_string();
-          let format = format.clone();
+      for size in &skipped {
+        for _ in 0..converters.len() {
+          progress.task_skipped(Phase::Convert, &input_path, UPSCALE_SKIPPED);
+        }
+        tracing::info!(input = input_path, size, "{}", UPSCALE_SKIPPED);
+      }
 
-          let semaphore = Arc::clone(&semaphore);
+      let mut outputs = Vec::new();
+      for (converter, logical_path, size, result) in encoded {
+        let result = match result {
+          Ok(()) if content_hash => hash::rename_with_content_hash(&logical_path).await,
+          Ok(()) => Ok(logical_path.clone()),
+          Err(e) => Err(e),
+        };
 
-          conversion_tasks.push(tokio::spawn(async move {
-            let _ = semaphore.acquire().await.unwrap();
+        let result = match result {
+          Ok(path) if manifest.is_some() => {
+            describe_asset(&input_path, &path, size, source.as_ref())
+              .await
+              .map(|asset| (logical_path, path, Some(asset)))
+          }
+          Ok(path) => Ok((logical_path, path, None)),
+          Err(e) if several_formats => {
+            Err(Error::new(e.kind(), format!("{}: {}", converter.name(), e)))
+          }
+          Err(e) => Err(e),
+        };
 
-            let result = match format.as_str() {
-              "webp" => ImageConverter::convert_to_webp(&input_path, &output_path).await,
-              "avif" => ImageConverter::convert_to_avif(&input_path, &output_path).await,
-              _ => unreachable!(),
-            };
+        let bytes = match &result {
+          Ok((_, path, _)) => fs::metadata(path).await.map_or(0, |m| m.len()),
+          Err(_) => 0,
+        };
 
-            match result {
-              Ok(_) => println!("[✅] Converted: {} -> {}", input_path, output_path),
-              Err(e) => eprintln!("[❌] Failed conversion: {}", e),
+        match &result {
+          Ok((_, path, _)) => {
+            progress.task_converted(Phase::Convert, &input_path, input_bytes, bytes);
+            if !quiet {
+              println!(
+                "[✅] Converted: {} -> {} ({})",
+                input_path,
+                path.display(),
+                size_change(input_bytes, bytes)
+              );
             }
-          }));
+          }
+          Err(e) => {
+            progress.task_failed(Phase::Convert, &input_path, e);
+            if !quiet {
+              eprintln!("[❌] Failed conversion: {}", e);
+            }
+          }
         }
+
+        outputs.push(result.map(|(logical_path, path, asset)| (logical_path, path, asset, bytes)));
+      }
+
+      (outputs, skipped, input_bytes)
+    });
+
+    conversion_tasks.push(async move { (path, task.await) });
+  }
+
+  let results = future::join_all(conversion_tasks).await;
+
+  let mut converted = Vec::new();
+  for (input_path, result) in results {
+    let (outputs, skipped, input_bytes) = match result {
+      Ok(outputs) => outputs,
+      Err(e) => {
+        progress.task_failed(Phase::Convert, input_path.display(), &e);
+        report.failed(input_path.display(), e);
+        continue;
+      }
+    };
+    for size in skipped {
+      report.skipped(
+        format!("{} ({})", input_path.display(), size),
+        UPSCALE_SKIPPED,
+      );
+    }
+    for output in outputs {
+      match output {
+        Ok((logical_path, path, asset, bytes)) => {
+          report.succeeded_with_sizes(input_path.display(), path.clone(), input_bytes, bytes);
+          converted.push((logical_path, path, asset));
+        }
+        Err(e) => report.failed(input_path.display(), e),
+      }
+    }
+  }
+  progress.phase_finished(Phase::Convert);
+
+  if options.content_hash {
+    let entries = converted
+      .iter()
+      .map(|(logical_path, path, _)| (logical_path.clone(), path.clone()))
+      .collect::<Vec<_>>();
+
+    match HashManifest::from_entries_in(&output_dir, &entries)
+      .write(&output_dir)
+      .await
+    {
+      Ok(path) if !quiet => println!("[✅] Wrote hash manifest: {}", path.display()),
+      Err(e) => {
+        tracing::error!("Failed to write hash manifest: {}", e);
+        report.failed(HASH_MANIFEST_FILENAME, e);
+      }
+      _ => {}
+    }
+  }
+
+  if let Some(format) = options.manifest {
+    let assets = converted
+      .into_iter()
+      .filter_map(|(_, _, asset)| asset)
+      .collect();
+
+    match AssetManifest::new(assets).write(&output_dir, format).await {
+      Ok(path) if !quiet => println!("[✅] Wrote manifest: {}", path.display()),
+      Err(e) => {
+        tracing::error!("Failed to write manifest: {}", e);
+        report.failed(format!("{}.{}", MANIFEST_FILENAME, format), e);
+      }
+      _ => {}
+    }
+  }
+
+  report
+}
+
+const UPSCALE_SKIPPED: &str = "larger than the input; pass --upscale to allow it";
+
+/// An output of one input: its converter, its path, and whether encoding succeeded.
+type Encoded = (
+  Arc<dyn Converter>,
+  PathBuf,
+  Option<(u32, u32)>,
+  Result<(), Error>,
+);
+
+/// Encodes `input_path` with every converter at every size in `resize`, decoding it only once.
+/// SVG inputs are rasterized with `svg`. Each output carries its pixel size when resizing
+/// determined it. Also returns the sizes skipped because they would upscale.
+fn encode_all(
+  input_path: &Path,
+  output_dir: &Path,
+  converters: &[Arc<dyn Converter>],
+  resize: &ResizeOptions,
+  svg: Option<&SvgRasterizer>,
+) -> (Vec<Encoded>, Vec<String>) {
+  let file_stem = input_path.file_stem().unwrap_or_default().to_string_lossy();
+  let output_path = |converter: &Arc<dyn Converter>, suffix: &str| {
+    output_dir.join(format!("{}{}.{}", file_stem, suffix, converter.extension()))
+  };
+  let encode = |source: &SourceImage, suffix: &str, size: Option<(u32, u32)>| {
+    converters
+      .iter()
+      .map(|converter| {
+        let span = tracing::info_span!(
+          "encode",
+          format = converter.name(),
+          input = %input_path.display(),
+          size = suffix.trim_start_matches('-')
+        );
+        let _entered = span.enter();
+        let output_path = output_path(converter, suffix);
+        let result = converter.convert_source(source, &output_path);
+        (Arc::clone(converter), output_path, size, result)
+      })
+      .collect::<Vec<_>>()
+  };
+
+  let source = SourceImage::new(input_path).with_svg(svg);
+  if !resize.is_enabled() {
+    return (encode(&source, "", None), Vec::new());
+  }
+
+  let image = match source.image() {
+    Ok(image) => image,
+    Err(e) => {
+      let failed = converters
+        .iter()
+        .map(|converter| {
+          let error = Error::new(e.kind(), e.to_string());
+          (
+            Arc::clone(converter),
+            output_path(converter, ""),
+            None,
+            Err(error),
+          )
+        })
+        .collect();
+      return (failed, Vec::new());
+    }
+  };
+
+  let (variants, skipped) = resize.plan(image.width(), image.height());
+  let mut encoded = Vec::new();
+  for variant in variants {
+    let size = Some((variant.width, variant.height));
+    encoded.extend(match variant.is_original(image.width(), image.height()) {
+      true => encode(&source, &variant.suffix, size),
+      false => {
+        let resized = SourceImage::modified(input_path, resize.apply(image, &variant));
+        encode(&resized, &variant.suffix, size)
+      }
+    });
+  }
+  (encoded, skipped)
+}
+
+/// Looks up each of the comma-separated `formats`, ignoring repeats.
+fn select_converters(
+  registry: &ConverterRegistry,
+  formats: &str,
+) -> Result<Vec<Arc<dyn Converter>>, Error> {
+  let mut selected: Vec<Arc<dyn Converter>> = Vec::new();
+
+  for name in formats
+    .split(',')
+    .map(str::trim)
+    .filter(|name| !name.is_empty())
+  {
+    let Some(converter) = registry.get(name) else {
+      let available = registry.names().collect::<Vec<_>>().join(", ");
+      return Err(Error::new(
+        ErrorKind::InvalidInput,
+        format!("Unsupported format: {} (available: {})", name, available),
+      ));
+    };
+
+    match selected
+      .iter()
+      .find(|other| other.extension() == converter.extension())
+    {
+      Some(other) if other.name() == converter.name() => {}
+      Some(other) => {
+        return Err(Error::new(
+          ErrorKind::InvalidInput,
+          format!(
+            "Formats {} and {} both write .{} files",
+            other.name(),
+            converter.name(),
+            converter.extension()
+          ),
+        ))
       }
+      None => selected.push(converter),
+    }
+  }
+
+  match selected.is_empty() {
+    true => Err(Error::new(
+      ErrorKind::InvalidInput,
+      "No output format given",
+    )),
+    false => Ok(selected),
+  }
+}
+
+/// Describes how much smaller or larger the output is than the input.
+fn size_change(input_bytes: u64, output_bytes: u64) -> String {
+  match output_bytes.cmp(&input_bytes) {
+    Ordering::Less => format!(
+      "saved {}, {}%",
+      HumanBytes(input_bytes - output_bytes),
+      (input_bytes - output_bytes) * 100 / input_bytes
+    ),
+    Ordering::Equal => "same size".to_string(),
+    Ordering::Greater => format!("{} larger", HumanBytes(output_bytes - input_bytes)),
+  }
+}
+
+/// Keeps the first of inputs that differ only in extension, such as `hero.png` and `hero.jpg`,
+/// since they would write the same output files.
+fn drop_same_names(inputs: Vec<PathBuf>, report: &mut RunReport) -> Vec<PathBuf> {
+  let mut first_by_name: HashMap<PathBuf, PathBuf> = HashMap::new();
+  let mut kept = Vec::new();
+
+  for path in inputs {
+    match first_by_name.entry(path.with_extension("")) {
+      Entry::Occupied(first) => {
+        let reason = format!("same output name as {}", first.get().display());
+        report.skipped(path.display(), reason);
+      }
+      Entry::Vacant(entry) => {
+        entry.insert(path.clone());
+        kept.push(path);
+      }
+    }
+  }
+  kept
+}
+
+/// Creates the SVG rasterizer if any of `inputs` is an SVG, loading its fonts on a blocking
+/// thread.
+async fn load_svg_rasterizer(
+  inputs: &[PathBuf],
+  options: &SvgOptions,
+) -> Result<Option<Arc<SvgRasterizer>>, Error> {
+  let (inputs, options) = (inputs.to_vec(), options.clone());
+  tokio::task::spawn_blocking(
+    move || match inputs.iter().any(|path| svg::is_svg_file(path)) {
+      true => Some(Arc::new(SvgRasterizer::new(options))),
+      false => None,
+    },
+  )
+  .await
+  .map_err(Error::other)
+}
+
+/// Lists the files to convert in `input_dir`, walking it on a blocking thread.
+async fn read_inputs(
+  input_dir: &Path,
+  output_dir: &Path,
+  options: &InputOptions,
+) -> Result<Inputs, Error> {
+  let (input_dir, output_dir) = (input_dir.to_path_buf(), output_dir.to_path_buf());
+  let options = options.clone();
+  tokio::task::spawn_blocking(move || options.find(&input_dir, Some(&output_dir)))
+    .await
+    .map_err(Error::other)?
+}
+
+/// Describes the output at `output_path`. `size` is the variant size it was resized to, used
+/// when the output format can't be inspected, and the Figma metadata in `source`, the input's
+/// entry in the manifest it was downloaded with, is carried over.
+async fn describe_asset(
+  input_path: &str,
+  output_path: &Path,
+  size: Option<(u32, u32)>,
+  source: Option<&AssetEntry>,
+) -> Result<AssetEntry, Error> {
+  let input_path = Path::new(input_path);
+  let name = input_path
+    .file_stem()
+    .and_then(|s| s.to_str())
+    .unwrap_or_default();
+  let mut asset = AssetEntry::describe(name, &input_path.to_string_lossy(), output_path).await?;
+  if let (Some(size), (0, 0)) = (size, (asset.width, asset.height)) {
+    (asset.width, asset.height) = size;
+  }
+  let asset = asset.with_source_file(input_path).await?;
+
+  Ok(match source {
+    Some(source) => AssetEntry {
+      node_id: source.node_id.clone(),
+      name: source.name.clone(),
+      page_path: source.page_path.clone(),
+      file_version: source.file_version.clone(),
+      scale: source.scale,
+      ..asset
+    },
+    None => asset,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+  use std::thread;
+  use std::time::Duration;
+
+  use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+  use tempfile::tempdir;
+
+  use super::*;
+  use crate::commands::output::OutputEvent;
+  use crate::commands::progress::ProgressEvent;
+  use crate::commands::report::TaskStatus;
+
+  /// Records how many images it is encoding at the same time.
+  #[derive(Default)]
+  struct CountingConverter {
+    running: AtomicUsize,
+    most_running: AtomicUsize,
+  }
+
+  impl Converter for Arc<CountingConverter> {
+    fn name(&self) -> &str {
+      "count"
+    }
+
+    fn extension(&self) -> &str {
+      "count"
+    }
+
+    fn mime_type(&self) -> &str {
+      "application/octet-stream"
+    }
+
+    fn encode(&self, _image: &DynamicImage) -> Result<Vec<u8>, Error> {
+      let running = self.running.fetch_add(1, AtomicOrdering::SeqCst) + 1;
+      self.most_running.fetch_max(running, AtomicOrdering::SeqCst);
+      thread::sleep(Duration::from_millis(50));
+      self.running.fetch_sub(1, AtomicOrdering::SeqCst);
+      Ok(Vec::new())
+    }
+  }
+
+  #[test]
+  fn test_select_converters() {
+    let registry = ConverterRegistry::with_defaults(&EncoderOptions::new());
+    let names = |formats: &str| {
+      select_converters(&registry, formats).map(|selected| {
+        selected
+          .iter()
+          .map(|converter| converter.name().to_string())
+          .collect::<Vec<_>>()
+      })
+    };
+
+    assert_eq!(names("webp").unwrap(), ["webp"]);
+    assert_eq!(
+      names("webp, AVIF,jpeg,webp").unwrap(),
+      ["webp", "avif", "jpeg"]
+    );
+    assert!(names("webp,gif").is_err());
+    assert!(names(" , ").is_err());
+  }
+
+  #[tokio::test]
+  async fn test_convert_to_several_formats() {
+    let input_dir = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+    RgbaImage::from_pixel(8, 8, Rgba([0, 128, 255, 255]))
+      .save(input_dir.path().join("logo.png"))
+      .unwrap();
 
-      if let Err(e) = future::try_join_all(conversion_tasks).await {
-        eprintln!("[❌] Some conversions failed: {}", e);
+    let report = execute(
+      input_dir.path().to_path_buf(),
+      output_dir.path().to_path_buf(),
+      "webp,jpeg,png".to_string(),
+      ConvertOptions::new().quiet(true),
+    )
+    .await;
+
+    assert!(!report.has_failures());
+    for name in ["logo.webp", "logo.jpg", "logo.png"] {
+      let encoded = std::fs::read(output_dir.path().join(name)).unwrap();
+      assert_eq!(
+        image::load_from_memory(&encoded).unwrap().dimensions(),
+        (8, 8)
+      );
+    }
+    // Sizes are reported for each output, so the bytes saved show up in JSON output too.
+    let input_bytes = std::fs::metadata(input_dir.path().join("logo.png"))
+      .unwrap()
+      .len();
+    for outcome in &report.outcomes {
+      let TaskStatus::Succeeded { output } = &outcome.status else {
+        panic!("{:?}", outcome);
+      };
+      assert_eq!(outcome.input_bytes, Some(input_bytes));
+      assert_eq!(
+        outcome.bytes,
+        Some(std::fs::metadata(output).unwrap().len())
+      );
+    }
+  }
+
+  #[tokio::test]
+  async fn test_convert_to_several_widths() {
+    let input_dir = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+    RgbaImage::from_pixel(400, 200, Rgba([0, 128, 255, 255]))
+      .save(input_dir.path().join("hero.png"))
+      .unwrap();
+
+    let resize = ResizeOptions::new().widths(vec![100, 200, 800]);
+    let report = execute(
+      input_dir.path().to_path_buf(),
+      output_dir.path().to_path_buf(),
+      "png".to_string(),
+      ConvertOptions::new().resize(resize).quiet(true),
+    )
+    .await;
+
+    assert_eq!((report.succeeded_count(), report.skipped_count()), (2, 1));
+    for (name, dimensions) in [("hero-100w.png", (100, 50)), ("hero-200w.png", (200, 100))] {
+      let encoded = std::fs::read(output_dir.path().join(name)).unwrap();
+      assert_eq!(
+        image::load_from_memory(&encoded).unwrap().dimensions(),
+        dimensions
+      );
+    }
+    assert!(!output_dir.path().join("hero-800w.png").exists());
+  }
+
+  #[tokio::test]
+  async fn test_manifest_records_variant_sizes() {
+    let input_dir = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+    RgbaImage::from_pixel(400, 200, Rgba([0, 128, 255, 255]))
+      .save(input_dir.path().join("hero.png"))
+      .unwrap();
+
+    // AVIF outputs can't be inspected, so their size has to come from the resize plan.
+    let resize = ResizeOptions::new().widths(vec![100, 200]);
+    let report = execute(
+      input_dir.path().to_path_buf(),
+      output_dir.path().to_path_buf(),
+      "avif".to_string(),
+      ConvertOptions::new()
+        .resize(resize)
+        .manifest(Some(ManifestFormat::Json))
+        .quiet(true),
+    )
+    .await;
+
+    assert!(!report.has_failures());
+    let manifest = AssetManifest::read(output_dir.path()).await.unwrap();
+    let mut sizes = manifest
+      .assets
+      .iter()
+      .map(|asset| (asset.width, asset.height))
+      .collect::<Vec<_>>();
+    sizes.sort();
+    assert_eq!(sizes, [(100, 50), (200, 100)]);
+  }
+
+  #[tokio::test]
+  async fn test_convert_skips_nested_output_dir() {
+    let input_dir = tempdir().unwrap();
+    let output_dir = input_dir.path().join("dist");
+    RgbaImage::new(4, 4)
+      .save(input_dir.path().join("logo.png"))
+      .unwrap();
+
+    for _ in 0..2 {
+      let report = execute(
+        input_dir.path().to_path_buf(),
+        output_dir.clone(),
+        "png".to_string(),
+        ConvertOptions::new()
+          .inputs(InputOptions::new().recursive(true))
+          .quiet(true),
+      )
+      .await;
+      assert_eq!((report.succeeded_count(), report.skipped_count()), (1, 0));
+    }
+    assert!(output_dir.join("logo.png").exists());
+    assert!(!output_dir.join("dist").exists());
+  }
+
+  #[tokio::test]
+  async fn test_manifest_matches_downloads_by_relative_path() {
+    let input_dir = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+    for path in ["hero.png", "icons/hero.png"] {
+      let path = input_dir.path().join(path);
+      std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+      RgbaImage::new(4, 4).save(path).unwrap();
+    }
+    let downloaded = AssetEntry {
+      node_id: Some("1:2".to_string()),
+      output_path: input_dir.path().join("hero.png"),
+      ..Default::default()
+    };
+    AssetManifest::new(vec![downloaded])
+      .write(input_dir.path(), ManifestFormat::Json)
+      .await
+      .unwrap();
+
+    execute(
+      input_dir.path().to_path_buf(),
+      output_dir.path().to_path_buf(),
+      "png".to_string(),
+      ConvertOptions::new()
+        .inputs(InputOptions::new().recursive(true))
+        .manifest(Some(ManifestFormat::Json))
+        .quiet(true),
+    )
+    .await;
+
+    let manifest = AssetManifest::read(output_dir.path()).await.unwrap();
+    let node_id = |output: &str| {
+      let asset = manifest
+        .assets
+        .iter()
+        .find(|asset| asset.output_path == output_dir.path().join(output))
+        .unwrap();
+      asset.node_id.clone()
+    };
+    assert_eq!(node_id("hero.png").as_deref(), Some("1:2"));
+    assert_eq!(node_id("icons/hero.png"), None);
+  }
+
+  #[tokio::test]
+  async fn test_ndjson_events_match_the_report() {
+    let input_dir = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+    RgbaImage::new(4, 4)
+      .save(input_dir.path().join("good.png"))
+      .unwrap();
+    // Recognized as a PNG, but it can't be decoded.
+    std::fs::write(
+      input_dir.path().join("broken.png"),
+      b"\x89PNG\r\n\x1a\ngarbage",
+    )
+    .unwrap();
+    // Fails before encoding: its output directory is taken by a file.
+    std::fs::create_dir(input_dir.path().join("icons")).unwrap();
+    RgbaImage::new(4, 4)
+      .save(input_dir.path().join("icons/logo.png"))
+      .unwrap();
+    std::fs::write(output_dir.path().join("icons"), b"").unwrap();
+
+    let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&events);
+    let progress = Progress::new(Arc::new(move |event: &ProgressEvent| {
+      if let Some(event) = OutputEvent::from_progress(event) {
+        let event = serde_json::to_value(&event).unwrap();
+        recorded
+          .lock()
+          .unwrap()
+          .push(event["event"].as_str().unwrap().to_string());
       }
+    }));
+    let report = execute(
+      input_dir.path().to_path_buf(),
+      output_dir.path().to_path_buf(),
+      "png".to_string(),
+      ConvertOptions::new()
+        .inputs(InputOptions::new().recursive(true))
+        .progress(progress)
+        .quiet(true),
+    )
+    .await;
+
+    let events = events.lock().unwrap();
+    let count = |name: &str| events.iter().filter(|event| *event == name).count();
+    assert_eq!((report.succeeded_count(), report.failed_count()), (1, 2));
+    assert_eq!(count("file_completed"), report.succeeded_count());
+    assert_eq!(count("file_failed"), report.failed_count());
+  }
+
+  #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+  async fn test_jobs_limits_concurrent_encodes() {
+    let input_dir = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+    for i in 0..6 {
+      RgbaImage::new(2, 2)
+        .save(input_dir.path().join(format!("{}.png", i)))
+        .unwrap();
+    }
+    let counter = Arc::new(CountingConverter::default());
+    let mut registry = ConverterRegistry::new();
+    registry.register(Arc::clone(&counter));
+
+    let report = execute(
+      input_dir.path().to_path_buf(),
+      output_dir.path().to_path_buf(),
+      "count".to_string(),
+      ConvertOptions::new()
+        .jobs(2)
+        .converters(registry)
+        .quiet(true),
+    )
+    .await;
+
+    assert_eq!(report.succeeded_count(), 6);
+    assert_eq!(counter.most_running.load(AtomicOrdering::SeqCst), 2);
+  }
+
+  #[tokio::test]
+  async fn test_convert_mirrors_subdirectories() {
+    let input_dir = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+    for path in ["hero.png", "icons/hero.PNG", "drafts/old.png"] {
+      let path = input_dir.path().join(path);
+      std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+      RgbaImage::new(4, 4)
+        .save_with_format(path, image::ImageFormat::Png)
+        .unwrap();
+    }
+
+    let inputs = InputOptions::new()
+      .recursive(true)
+      .exclude(vec!["drafts".to_string()]);
+    let report = execute(
+      input_dir.path().to_path_buf(),
+      output_dir.path().to_path_buf(),
+      "png".to_string(),
+      ConvertOptions::new()
+        .inputs(inputs)
+        .content_hash(true)
+        .quiet(true),
+    )
+    .await;
+
+    assert_eq!(report.succeeded_count(), 2);
+    assert!(!output_dir.path().join("drafts").exists());
+    let hash_manifest: std::collections::BTreeMap<String, String> = serde_json::from_slice(
+      &std::fs::read(output_dir.path().join(HASH_MANIFEST_FILENAME)).unwrap(),
+    )
+    .unwrap();
+    for name in ["hero.png", "icons/hero.png"] {
+      assert!(output_dir.path().join(&hash_manifest[name]).exists());
     }
-    Err(e) => eprintln!("[❌] Failed to read input directory: {}", e),
+  }
+
+  #[tokio::test]
+  async fn test_convert_other_input_formats() {
+    let input_dir = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+
+    // A 4x2 JPEG whose EXIF orientation (6) says to rotate it 90 degrees clockwise.
+    let mut jpeg = Vec::new();
+    image::DynamicImage::new_rgb8(4, 2)
+      .write_to(
+        &mut std::io::Cursor::new(&mut jpeg),
+        image::ImageFormat::Jpeg,
+      )
+      .unwrap();
+    let exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x06\0\0\0\0\0\0";
+    let mut app1 = vec![0xff, 0xe1];
+    app1.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+    app1.extend_from_slice(exif);
+    jpeg.splice(2..2, app1);
+    std::fs::write(input_dir.path().join("photo.jpeg"), jpeg).unwrap();
+
+    RgbaImage::from_fn(3, 3, |x, _| Rgba([255, 0, 0, (x * 100) as u8]))
+      .save(input_dir.path().join("loader.gif"))
+      .unwrap();
+    RgbaImage::new(1, 1)
+      .save(input_dir.path().join("loader.png"))
+      .unwrap();
+
+    let report = execute(
+      input_dir.path().to_path_buf(),
+      output_dir.path().to_path_buf(),
+      "png".to_string(),
+      ConvertOptions::new().quiet(true),
+    )
+    .await;
+
+    assert_eq!((report.succeeded_count(), report.skipped_count()), (2, 1));
+    let photo = image::open(output_dir.path().join("photo.png")).unwrap();
+    assert_eq!(photo.dimensions(), (2, 4));
+    let loader = image::open(output_dir.path().join("loader.png")).unwrap();
+    assert_eq!(loader.get_pixel(0, 0).0[3], 0);
+    assert_eq!(loader.get_pixel(2, 0).0[3], 255);
+  }
+
+  #[tokio::test]
+  async fn test_convert_rasterizes_svg() {
+    let input_dir = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+    std::fs::write(
+      input_dir.path().join("logo.svg"),
+      r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 20 10">
+        <circle cx="5" cy="5" r="5" fill="#0000ff"/>
+      </svg>"##,
+    )
+    .unwrap();
+
+    let report = execute(
+      input_dir.path().to_path_buf(),
+      output_dir.path().to_path_buf(),
+      "png".to_string(),
+      ConvertOptions::new()
+        .svg(SvgOptions::new().width(Some(80)).system_fonts(false))
+        .quiet(true),
+    )
+    .await;
+
+    assert_eq!(report.succeeded_count(), 1);
+    let logo = image::open(output_dir.path().join("logo.png")).unwrap();
+    assert_eq!(logo.dimensions(), (80, 40));
+    assert_eq!(logo.get_pixel(20, 20).0, [0, 0, 255, 255]);
+    assert_eq!(logo.get_pixel(60, 20).0[3], 0);
   }
 }
@@ -1,20 +1,92 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use downloader::ImageDownloader;
-use extractor::FigmaImageExtractor;
+use extractor::{FigmaImage, FigmaImageExtractor};
 use futures::future;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use tokio::fs;
 
+use crate::commands::progress::{Phase, Progress};
+use crate::commands::report::RunReport;
 use crate::config::FigmaConfig;
-use crate::core::{downloader, extractor};
-use crate::utils::filename;
+use crate::core::manifest::{AssetEntry, AssetManifest, ManifestFormat, MANIFEST_FILENAME};
+use crate::core::{density, downloader, extractor, validator};
+use crate::utils::hash::{self, HashManifest, HASH_MANIFEST_FILENAME};
+use crate::utils::{filename, redact};
 
-#[derive(Default, Deserialize, Serialize)]
+#[derive(Deserialize, Serialize)]
 pub struct DownloadOptions {
   #[serde(default)]
   quiet: bool,
+  #[serde(default)]
+  content_hash: bool,
+  #[serde(default)]
+  manifest: Option<ManifestFormat>,
+  #[serde(default = "default_scale")]
+  scale: f32,
+  #[serde(default)]
+  derive_scales: Vec<f32>,
+  #[serde(default)]
+  sharpen: f32,
+  #[serde(default = "default_connect_timeout")]
+  connect_timeout: u64,
+  #[serde(default = "default_read_timeout")]
+  read_timeout: u64,
+  #[serde(default = "default_api_timeout")]
+  api_timeout: u64,
+  #[serde(default = "default_retries")]
+  retries: u32,
+  #[serde(default = "default_jobs")]
+  jobs: usize,
+  #[serde(default)]
+  max_per_host: Option<usize>,
+  #[serde(skip)]
+  progress: Progress,
+}
+
+fn default_scale() -> f32 {
+  1.0
+}
+
+fn default_connect_timeout() -> u64 {
+  downloader::DEFAULT_CONNECT_TIMEOUT.as_secs()
+}
+
+fn default_read_timeout() -> u64 {
+  downloader::DEFAULT_READ_TIMEOUT.as_secs()
+}
+
+fn default_api_timeout() -> u64 {
+  extractor::DEFAULT_API_TIMEOUT.as_secs()
+}
+
+fn default_retries() -> u32 {
+  downloader::DEFAULT_MAX_RETRIES
+}
+
+fn default_jobs() -> usize {
+  downloader::DEFAULT_JOBS
+}
+
+impl Default for DownloadOptions {
+  fn default() -> Self {
+    Self {
+      quiet: false,
+      content_hash: false,
+      manifest: None,
+      scale: default_scale(),
+      derive_scales: Vec::new(),
+      sharpen: 0.0,
+      connect_timeout: default_connect_timeout(),
+      read_timeout: default_read_timeout(),
+      api_timeout: default_api_timeout(),
+      retries: default_retries(),
+      jobs: default_jobs(),
+      max_per_host: None,
+      progress: Progress::default(),
+    }
+  }
 }
 
 impl DownloadOptions {
@@ -26,63 +98,504 @@ impl DownloadOptions {
     self.quiet = quiet;
     self
   }
+
+  pub fn content_hash(mut self, content_hash: bool) -> Self {
+    self.content_hash = content_hash;
+    self
+  }
+
+  pub fn manifest(mut self, manifest: Option<ManifestFormat>) -> Self {
+    self.manifest = manifest;
+    self
+  }
+
+  pub fn scale(mut self, scale: f32) -> Self {
+    self.scale = scale;
+    self
+  }
+
+  /// Lower scales to downsample locally from the render at `scale`, instead of asking Figma to
+  /// render each of them.
+  pub fn derive_scales(mut self, derive_scales: Vec<f32>) -> Self {
+    self.derive_scales = derive_scales;
+    self
+  }
+
+  /// Strength of the sharpening applied to derived scales, 0 for none.
+  pub fn sharpen(mut self, sharpen: f32) -> Self {
+    self.sharpen = sharpen;
+    self
+  }
+
+  /// Connect timeout in seconds.
+  pub fn connect_timeout(mut self, connect_timeout: u64) -> Self {
+    self.connect_timeout = connect_timeout;
+    self
+  }
+
+  /// Read timeout in seconds.
+  pub fn read_timeout(mut self, read_timeout: u64) -> Self {
+    self.read_timeout = read_timeout;
+    self
+  }
+
+  /// Timeout in seconds for each Figma API request, which may include rendering the images.
+  pub fn api_timeout(mut self, api_timeout: u64) -> Self {
+    self.api_timeout = api_timeout;
+    self
+  }
+
+  pub fn retries(mut self, retries: u32) -> Self {
+    self.retries = retries;
+    self
+  }
+
+  pub fn jobs(mut self, jobs: usize) -> Self {
+    self.jobs = jobs;
+    self
+  }
+
+  pub fn max_per_host(mut self, max_per_host: Option<usize>) -> Self {
+    self.max_per_host = max_per_host;
+    self
+  }
+
+  pub fn progress(mut self, progress: Progress) -> Self {
+    self.progress = progress;
+    self
+  }
 }
 
-pub async fn execute(download_dir: PathBuf, options: DownloadOptions) {
+struct Downloaded {
+  logical_path: PathBuf,
+  path: PathBuf,
+  asset: Option<AssetEntry>,
+}
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+pub async fn execute(download_dir: PathBuf, mut options: DownloadOptions) -> RunReport {
+  let mut report = RunReport::new();
+
+  // Written this way round so NaN is rejected too.
+  if let Some(scale) = options
+    .derive_scales
+    .iter()
+    .find(|&&scale| !(scale > 0.0 && scale < options.scale))
+  {
+    let message = format!(
+      "Derived scale {} must be above 0 and below the rendered scale {}",
+      scale, options.scale
+    );
+    tracing::error!("{}", message);
+    report.failed("derive scales", message);
+    return report;
+  }
+  options.derive_scales = unique_scales(options.derive_scales);
+
   if let Err(e) = fs::create_dir_all(&download_dir).await {
-    if !options.quiet {
-      eprintln!("[❌]Failed to create download directory: {}", e);
-    }
-    return;
+    tracing::error!("Failed to create download directory: {}", e);
+    report.failed(download_dir.display(), e);
+    return report;
   }
 
   let config = FigmaConfig::new();
-  let extractor = FigmaImageExtractor::new(Client::new(), config);
+  let connect_timeout = Duration::from_secs(options.connect_timeout);
+  // The API gets its own client: a render request may legitimately take longer than an image
+  // download is allowed to stall.
+  let clients =
+    downloader::build_client(connect_timeout, Duration::from_secs(options.read_timeout)).and_then(
+      |client| {
+        let api_timeout = Duration::from_secs(options.api_timeout);
+        Ok((
+          client,
+          downloader::build_client(connect_timeout, api_timeout)?,
+        ))
+      },
+    );
+  let (client, api_client) = match clients {
+    Ok(clients) => clients,
+    Err(e) => {
+      tracing::error!("Failed to build the HTTP client: {}", e);
+      report.failed("HTTP client", e);
+      return report;
+    }
+  };
+  let extractor = FigmaImageExtractor::new(api_client, config).with_scale(options.scale);
+  let downloader = ImageDownloader::with_client(client)
+    .max_retries(options.retries)
+    .jobs(options.jobs)
+    .max_per_host(options.max_per_host);
 
-  match extractor.extract().await {
-    Ok(images) => {
-      let downloads = images
-        .into_iter()
-        .filter_map(|(_node_id, image_url, name)| {
-          image_url.as_str().map(|url| {
-            let url = url.to_string();
-
-            let sanitized_name = filename::sanitize(&name);
-            let png_filename = download_dir.join(format!("{}.png", sanitized_name));
-            let png_path = png_filename.to_str().unwrap().to_string();
-
-            let downloader = ImageDownloader::new();
-
-            async move {
-              match downloader.download(&url, &png_path).await {
-                Ok(path) => {
-                  if !options.quiet {
-                    println!("✅ Downloaded: {}", path);
-                  }
-                  Ok(())
-                }
-                Err(error) => {
-                  if !options.quiet {
-                    eprintln!("❌ Failed to download {}: {}", png_path, error);
-                  }
-                  Err(error)
-                }
-              }
-            }
-          })
-        })
-        .collect::<Vec<_>>();
+  let progress = &options.progress;
+
+  progress.phase_started(Phase::FetchFile, None);
+  let figma_images = match extractor.get_image_nodes().await {
+    Ok((file_version, image_nodes)) => {
+      progress.phase_finished(Phase::FetchFile);
+      progress.phase_started(Phase::Render, Some(image_nodes.len() as u64));
+      extractor.render_images(file_version, image_nodes).await
+    }
+    Err(e) => Err(e),
+  };
+
+  let figma_images = match figma_images {
+    Ok(figma_images) => {
+      progress.phase_finished(Phase::Render);
+      figma_images
+    }
+    Err(e) => {
+      tracing::error!("Failed to request figma API: {}", e);
+      report.failed("Figma API", e);
+      return report;
+    }
+  };
+
+  let file_version = figma_images.file_version.as_deref();
+  let mut downloads = Vec::new();
+
+  progress.phase_started(Phase::Download, Some(figma_images.images.len() as u64));
+
+  for image in &figma_images.images {
+    let Some(url) = image.url.as_str() else {
+      let reason = "Figma returned no render URL";
+      progress.task_skipped(Phase::Download, &image.node.name, reason);
+      report.skipped(&image.node.name, reason);
+      continue;
+    };
 
-      if let Err(e) = future::try_join_all(downloads).await {
-        if !options.quiet {
-          eprintln!("[❌] Some downloads failed: {}", e);
+    let sanitized_name = filename::sanitize(&image.node.name);
+    // Once there are several densities, each file is named after its own.
+    let suffix = match options.derive_scales.is_empty() {
+      true => String::new(),
+      false => density::scale_suffix(options.scale),
+    };
+    let png_filename = download_dir.join(format!("{}{}.png", sanitized_name, suffix));
+    let png_path = png_filename.to_str().unwrap().to_string();
+
+    let downloader = &downloader;
+    let options = &options;
+    let download_dir = &download_dir;
+
+    let expected_size = validator::expected_size(image.node.bounds, options.scale);
+
+    downloads.push(async move {
+      progress.task_started(Phase::Download, png_filename.display());
+
+      let result = async {
+        let (path, warnings) = download_image(
+          downloader,
+          url,
+          &png_path,
+          expected_size,
+          options.content_hash,
+        )
+        .await?;
+        let asset = match options.manifest {
+          Some(_) => Some(describe_asset(image, url, &path, file_version, options).await?),
+          None => None,
+        };
+        Ok::<_, BoxError>((path, asset, warnings))
+      }
+      .await;
+
+      match &result {
+        Ok((path, _, _)) => {
+          let bytes = fs::metadata(path).await.map_or(0, |m| m.len());
+          progress.task_completed(Phase::Download, png_filename.display(), bytes);
+        }
+        Err(error) => progress.task_failed(Phase::Download, png_filename.display(), error),
+      }
+
+      match &result {
+        Ok((path, _, warnings)) if !options.quiet => {
+          println!("✅ Downloaded: {}", path.display());
+          for warning in warnings {
+            eprintln!("⚠️  Suspicious render {}: {}", path.display(), warning);
+          }
+        }
+        Err(error) if !options.quiet => {
+          eprintln!("❌ Failed to download {}: {}", png_path, error)
         }
+        _ => {}
+      }
+
+      let derived = match &result {
+        Ok((path, _, _)) if !options.derive_scales.is_empty() => {
+          derive_densities(image, url, download_dir, path, file_version, options).await
+        }
+        _ => Vec::new(),
+      };
+
+      (png_filename, result, derived)
+    });
+  }
+
+  let results = future::join_all(downloads).await;
+  progress.phase_finished(Phase::Download);
+
+  let mut downloaded = Vec::new();
+  for (png_filename, result, derived) in results {
+    for (logical_path, result) in derived {
+      match result {
+        Ok((path, asset)) => {
+          report.succeeded(logical_path.display(), path.clone());
+          downloaded.push(Downloaded {
+            logical_path,
+            path,
+            asset,
+          });
+        }
+        Err(error) => report.failed(logical_path.display(), error),
       }
     }
-    Err(e) => {
-      if !options.quiet {
-        eprintln!("[❌] Failed to request figma API: {}", e);
+    match result {
+      Ok((path, asset, warnings)) => {
+        report.succeeded_with_warnings(png_filename.display(), path.clone(), warnings);
+        downloaded.push(Downloaded {
+          logical_path: png_filename,
+          path,
+          asset,
+        });
+      }
+      Err(error) => report.failed(png_filename.display(), error),
+    }
+  }
+
+  if options.content_hash {
+    let entries = downloaded
+      .iter()
+      .map(|d| (d.logical_path.clone(), d.path.clone()))
+      .collect::<Vec<_>>();
+
+    match HashManifest::from_entries(&entries)
+      .write(&download_dir)
+      .await
+    {
+      Ok(path) if !options.quiet => println!("[✅] Wrote hash manifest: {}", path.display()),
+      Err(e) => {
+        tracing::error!("Failed to write hash manifest: {}", e);
+        report.failed(HASH_MANIFEST_FILENAME, e);
       }
+      _ => {}
     }
   }
+
+  if let Some(format) = options.manifest {
+    let assets = downloaded.into_iter().filter_map(|d| d.asset).collect();
+
+    match AssetManifest::new(assets)
+      .write(&download_dir, format)
+      .await
+    {
+      Ok(path) if !options.quiet => println!("[✅] Wrote manifest: {}", path.display()),
+      Err(e) => {
+        tracing::error!("Failed to write manifest: {}", e);
+        report.failed(format!("{}.{}", MANIFEST_FILENAME, format), e);
+      }
+      _ => {}
+    }
+  }
+
+  report
+}
+
+async fn download_image(
+  downloader: &ImageDownloader,
+  url: &str,
+  png_path: &str,
+  expected_size: Option<(u32, u32)>,
+  content_hash: bool,
+) -> Result<(PathBuf, Vec<String>), BoxError> {
+  let validated = downloader
+    .download_validated(url, png_path, expected_size)
+    .await?;
+  let path = PathBuf::from(png_path);
+
+  if content_hash {
+    return Ok((
+      hash::rename_with_content_hash(&path).await?,
+      validated.warnings,
+    ));
+  }
+
+  Ok((path, validated.warnings))
+}
+
+/// Drops repeats from `scales`, which would only write the same file twice, keeping their order.
+fn unique_scales(scales: Vec<f32>) -> Vec<f32> {
+  let mut unique = Vec::new();
+  for scale in scales {
+    if !unique.contains(&scale) {
+      unique.push(scale);
+    }
+  }
+  unique
+}
+
+/// Downsamples the download at `source_path` to each of `options.derive_scales`, returning the logical
+/// path of every derived file with its final path and manifest entry.
+async fn derive_densities(
+  image: &FigmaImage,
+  url: &str,
+  download_dir: &Path,
+  source_path: &Path,
+  file_version: Option<&str>,
+  options: &DownloadOptions,
+) -> Vec<(PathBuf, Result<(PathBuf, Option<AssetEntry>), BoxError>)> {
+  let sanitized_name = filename::sanitize(&image.node.name);
+  let targets = options
+    .derive_scales
+    .iter()
+    .map(|&scale| {
+      let file_name = format!("{}{}.png", sanitized_name, density::scale_suffix(scale));
+      (scale, download_dir.join(file_name))
+    })
+    .collect::<Vec<_>>();
+
+  let written = {
+    let (path, targets) = (source_path.to_path_buf(), targets.clone());
+    let (bounds, rendered_scale, sharpen) = (image.node.bounds, options.scale, options.sharpen);
+    tokio::task::spawn_blocking(move || {
+      let source = image::open(&path).map_err(|e| e.to_string())?;
+      Ok::<_, String>(
+        targets
+          .into_iter()
+          .map(|(scale, target)| {
+            // Prefer the size Figma would have rendered, so derived files match real renders.
+            let (width, height) = validator::expected_size(bounds, scale).unwrap_or_else(|| {
+              let ratio = scale / rendered_scale;
+              (
+                (source.width() as f32 * ratio).round().max(1.0) as u32,
+                (source.height() as f32 * ratio).round().max(1.0) as u32,
+              )
+            });
+            let result = density::downsample(&source, width, height, sharpen)
+              .save(&target)
+              .map_err(|e| e.to_string());
+            (scale, target, result)
+          })
+          .collect::<Vec<_>>(),
+      )
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|written| written)
+  };
+
+  let written = match written {
+    Ok(written) => written,
+    Err(error) => {
+      return targets
+        .into_iter()
+        .map(|(_, target)| {
+          let error = format!("Failed to read {}: {}", source_path.display(), error);
+          (target, Err(error.into()))
+        })
+        .collect();
+    }
+  };
+
+  let mut derived = Vec::new();
+  for (scale, logical_path, result) in written {
+    let result = async {
+      result?;
+      let path = match options.content_hash {
+        true => hash::rename_with_content_hash(&logical_path).await?,
+        false => logical_path.clone(),
+      };
+      let asset = match options.manifest {
+        Some(_) => Some(AssetEntry {
+          scale: Some(scale),
+          derived_from: Some(source_path.to_path_buf()),
+          ..describe_asset(image, url, &path, file_version, options).await?
+        }),
+        None => None,
+      };
+      Ok::<_, BoxError>((path, asset))
+    }
+    .await;
+
+    match &result {
+      Ok((path, _)) if !options.quiet => println!("✅ Derived: {}", path.display()),
+      Err(error) if !options.quiet => {
+        eprintln!("❌ Failed to derive {}: {}", logical_path.display(), error)
+      }
+      _ => {}
+    }
+    derived.push((logical_path, result));
+  }
+  derived
+}
+
+async fn describe_asset(
+  image: &FigmaImage,
+  url: &str,
+  path: &Path,
+  file_version: Option<&str>,
+  options: &DownloadOptions,
+) -> Result<AssetEntry, BoxError> {
+  // Pre-signed render URLs grant access until they expire, so the manifest keeps only the path.
+  let source = redact::url_without_query(url);
+  let asset = AssetEntry::describe(&image.node.name, source, path).await?;
+
+  Ok(AssetEntry {
+    node_id: Some(image.node.id.clone()),
+    page_path: Some(image.node.page_path.clone()),
+    file_version: file_version.map(str::to_string),
+    scale: Some(options.scale),
+    ..asset
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use tempfile::tempdir;
+
+  use super::*;
+
+  #[tokio::test]
+  async fn test_rejects_invalid_derive_scales() {
+    let download_dir = tempdir().unwrap();
+    for scale in [0.0, 3.0, f32::NAN, f32::INFINITY] {
+      let options = DownloadOptions::new()
+        .scale(3.0)
+        .derive_scales(vec![1.0, scale])
+        .quiet(true);
+      let report = execute(download_dir.path().to_path_buf(), options).await;
+      assert!(report.has_failures(), "{} was accepted", scale);
+    }
+  }
+
+  #[tokio::test]
+  async fn test_manifest_leaves_out_render_url_query() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().join("hero.png");
+    image::RgbaImage::new(2, 2).save(&path).unwrap();
+    let image = FigmaImage {
+      node: extractor::ImageNode {
+        id: "1:2".to_string(),
+        name: "hero".to_string(),
+        page_path: "Page 1".to_string(),
+        bounds: None,
+      },
+      url: serde_json::Value::Null,
+    };
+    let url = "https://s3.amazonaws.com/images/hero.png?X-Amz-Signature=secret";
+
+    let asset = describe_asset(&image, url, &path, None, &DownloadOptions::new())
+      .await
+      .unwrap();
+    let manifest = AssetManifest::new(vec![asset])
+      .render(ManifestFormat::Json)
+      .unwrap();
+
+    assert!(manifest.contains("https://s3.amazonaws.com/images/hero.png"));
+    assert!(!manifest.contains('?'), "{}", manifest);
+  }
+
+  #[test]
+  fn test_unique_scales() {
+    assert_eq!(unique_scales(vec![2.0, 1.0, 2.0, 1.0]), [2.0, 1.0]);
+  }
 }
@@ -1,2 +1,5 @@
 pub mod convert;
 pub mod download;
+pub mod output;
+pub mod progress;
+pub mod report;
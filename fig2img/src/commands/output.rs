@@ -0,0 +1,268 @@
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+use crate::commands::progress::{Phase, ProgressEvent, ProgressListener};
+use crate::commands::report::{RunReport, TaskOutcome};
+
+/// Bumped whenever a field is removed or changes meaning. Adding fields is not a breaking change.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+  /// Human-readable lines and progress bars.
+  #[default]
+  Text,
+  /// A single JSON document printed when the run finishes.
+  Json,
+  /// One JSON object per line, printed as events happen.
+  Ndjson,
+}
+
+impl FromStr for OutputFormat {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_ascii_lowercase().as_str() {
+      "text" => Ok(Self::Text),
+      "json" => Ok(Self::Json),
+      "ndjson" => Ok(Self::Ndjson),
+      _ => Err(format!("Unsupported output format: {}", s)),
+    }
+  }
+}
+
+impl fmt::Display for OutputFormat {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Self::Text => "text",
+      Self::Json => "json",
+      Self::Ndjson => "ndjson",
+    })
+  }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum OutputEvent {
+  FileStarted {
+    phase: Phase,
+    file: String,
+  },
+  FileCompleted {
+    phase: Phase,
+    file: String,
+    bytes: u64,
+    /// Size of the converted input, so that the bytes saved can be worked out.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    input_bytes: Option<u64>,
+  },
+  FileSkipped {
+    phase: Phase,
+    file: String,
+    reason: String,
+  },
+  FileFailed {
+    phase: Phase,
+    file: String,
+    reason: String,
+  },
+  Summary(Summary),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+  pub schema_version: u32,
+  pub succeeded: usize,
+  pub skipped: usize,
+  pub failed: usize,
+  pub warnings: usize,
+  pub outcomes: Vec<TaskOutcome>,
+}
+
+impl From<&RunReport> for Summary {
+  fn from(report: &RunReport) -> Self {
+    Self {
+      schema_version: SCHEMA_VERSION,
+      succeeded: report.succeeded_count(),
+      skipped: report.skipped_count(),
+      failed: report.failed_count(),
+      warnings: report.warning_count(),
+      outcomes: report.outcomes.clone(),
+    }
+  }
+}
+
+impl OutputEvent {
+  /// The event printed for a progress event, if it is one that gets printed.
+  pub fn from_progress(event: &ProgressEvent) -> Option<Self> {
+    Some(match event.clone() {
+      ProgressEvent::TaskStarted { phase, item } => Self::FileStarted { phase, file: item },
+      ProgressEvent::TaskCompleted {
+        phase,
+        item,
+        bytes,
+        input_bytes,
+      } => Self::FileCompleted {
+        phase,
+        file: item,
+        bytes,
+        input_bytes,
+      },
+      ProgressEvent::TaskSkipped {
+        phase,
+        item,
+        reason,
+      } => Self::FileSkipped {
+        phase,
+        file: item,
+        reason,
+      },
+      ProgressEvent::TaskFailed {
+        phase,
+        item,
+        reason,
+      } => Self::FileFailed {
+        phase,
+        file: item,
+        reason,
+      },
+      ProgressEvent::PhaseStarted { .. } | ProgressEvent::PhaseFinished { .. } => return None,
+    })
+  }
+}
+
+#[derive(Serialize)]
+struct JsonDocument<'a> {
+  schema_version: u32,
+  events: &'a [OutputEvent],
+  summary: Summary,
+}
+
+/// Writes progress events as JSON to stdout: line by line for `ndjson`, or as one document
+/// once [`finish`](Self::finish) is called for `json`.
+pub struct JsonOutput {
+  format: OutputFormat,
+  events: Mutex<Vec<OutputEvent>>,
+}
+
+impl JsonOutput {
+  pub fn new(format: OutputFormat) -> Self {
+    Self {
+      format,
+      events: Mutex::new(Vec::new()),
+    }
+  }
+
+  pub fn finish(&self, report: &RunReport) {
+    let summary = Summary::from(report);
+
+    match self.format {
+      OutputFormat::Ndjson => Self::write_line(&OutputEvent::Summary(summary)),
+      _ => {
+        let events = self.events.lock().unwrap();
+        let document = JsonDocument {
+          schema_version: SCHEMA_VERSION,
+          events: &events,
+          summary,
+        };
+        Self::write_line(&document);
+      }
+    }
+  }
+
+  fn write_line(value: &impl Serialize) {
+    if let Ok(line) = serde_json::to_string(value) {
+      let mut stdout = std::io::stdout().lock();
+      let _ = writeln!(stdout, "{}", line);
+    }
+  }
+}
+
+impl ProgressListener for JsonOutput {
+  fn on_event(&self, event: &ProgressEvent) {
+    let Some(event) = OutputEvent::from_progress(event) else {
+      return;
+    };
+
+    match self.format {
+      OutputFormat::Ndjson => Self::write_line(&event),
+      _ => self.events.lock().unwrap().push(event),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+
+  use serde_json::json;
+
+  use super::*;
+
+  #[test]
+  fn test_output_format_from_str() {
+    assert_eq!("json".parse(), Ok(OutputFormat::Json));
+    assert_eq!("NDJSON".parse(), Ok(OutputFormat::Ndjson));
+    assert!("xml".parse::<OutputFormat>().is_err());
+  }
+
+  #[test]
+  fn test_output_event_schema() {
+    let event = OutputEvent::from_progress(&ProgressEvent::TaskCompleted {
+      phase: Phase::Convert,
+      item: "in/hero.png".to_string(),
+      bytes: 512,
+      input_bytes: Some(2048),
+    })
+    .unwrap();
+
+    assert_eq!(
+      serde_json::to_value(&event).unwrap(),
+      json!({
+        "event": "file_completed",
+        "phase": "convert",
+        "file": "in/hero.png",
+        "bytes": 512,
+        "input_bytes": 2048
+      })
+    );
+
+    assert!(OutputEvent::from_progress(&ProgressEvent::PhaseFinished {
+      phase: Phase::Convert
+    })
+    .is_none());
+  }
+
+  #[test]
+  fn test_summary_schema() {
+    let mut report = RunReport::new();
+    report.succeeded_with_sizes("in/hero.png", PathBuf::from("out/hero.webp"), 2048, 512);
+    report.failed("in/logo.png", "converter reported failure");
+
+    assert_eq!(
+      serde_json::to_value(OutputEvent::Summary(Summary::from(&report))).unwrap(),
+      json!({
+        "event": "summary",
+        "schema_version": 1,
+        "succeeded": 1,
+        "skipped": 0,
+        "failed": 1,
+        "warnings": 0,
+        "outcomes": [
+          {
+            "item": "in/hero.png",
+            "status": "succeeded",
+            "output": "out/hero.webp",
+            "input_bytes": 2048,
+            "bytes": 512
+          },
+          { "item": "in/logo.png", "status": "failed", "reason": "converter reported failure" }
+        ]
+      })
+    );
+  }
+}
@@ -0,0 +1,199 @@
+use std::fmt;
+use std::sync::Arc;
+
+use serde::Serialize;
+
+/// Stages of a run, in the order they happen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+  /// Fetching the Figma file to find image nodes.
+  FetchFile,
+  /// Waiting for Figma to render the image nodes.
+  Render,
+  Download,
+  Convert,
+}
+
+impl fmt::Display for Phase {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Self::FetchFile => "Fetch file",
+      Self::Render => "Render",
+      Self::Download => "Download",
+      Self::Convert => "Convert",
+    })
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgressEvent {
+  /// `total` is the number of tasks in the phase, when known up front.
+  PhaseStarted {
+    phase: Phase,
+    total: Option<u64>,
+  },
+  TaskStarted {
+    phase: Phase,
+    item: String,
+  },
+  /// `bytes` is the size of the file the task produced, and `input_bytes` the size of the file a
+  /// conversion read.
+  TaskCompleted {
+    phase: Phase,
+    item: String,
+    bytes: u64,
+    input_bytes: Option<u64>,
+  },
+  TaskSkipped {
+    phase: Phase,
+    item: String,
+    reason: String,
+  },
+  TaskFailed {
+    phase: Phase,
+    item: String,
+    reason: String,
+  },
+  PhaseFinished {
+    phase: Phase,
+  },
+}
+
+/// Receives progress events from `download` and `convert`. Implemented for closures, so
+/// `|event: &ProgressEvent| println!("{:?}", event)` is a valid listener.
+pub trait ProgressListener: Send + Sync {
+  fn on_event(&self, event: &ProgressEvent);
+}
+
+impl<F> ProgressListener for F
+where
+  F: Fn(&ProgressEvent) + Send + Sync,
+{
+  fn on_event(&self, event: &ProgressEvent) {
+    self(event)
+  }
+}
+
+/// Cheaply cloneable handle that forwards events to an optional listener.
+#[derive(Clone, Default)]
+pub struct Progress {
+  listener: Option<Arc<dyn ProgressListener>>,
+}
+
+impl Progress {
+  pub fn new(listener: Arc<dyn ProgressListener>) -> Self {
+    Self {
+      listener: Some(listener),
+    }
+  }
+
+  pub fn emit(&self, event: ProgressEvent) {
+    if let Some(listener) = &self.listener {
+      listener.on_event(&event);
+    }
+  }
+
+  pub fn phase_started(&self, phase: Phase, total: Option<u64>) {
+    self.emit(ProgressEvent::PhaseStarted { phase, total });
+  }
+
+  pub fn phase_finished(&self, phase: Phase) {
+    self.emit(ProgressEvent::PhaseFinished { phase });
+  }
+
+  pub fn task_started(&self, phase: Phase, item: impl fmt::Display) {
+    let item = item.to_string();
+    self.emit(ProgressEvent::TaskStarted { phase, item });
+  }
+
+  pub fn task_completed(&self, phase: Phase, item: impl fmt::Display, bytes: u64) {
+    let item = item.to_string();
+    self.emit(ProgressEvent::TaskCompleted {
+      phase,
+      item,
+      bytes,
+      input_bytes: None,
+    });
+  }
+
+  /// Like [`task_completed`](Self::task_completed), for a task that turned `input_bytes` into
+  /// `bytes`.
+  pub fn task_converted(
+    &self,
+    phase: Phase,
+    item: impl fmt::Display,
+    input_bytes: u64,
+    bytes: u64,
+  ) {
+    let item = item.to_string();
+    self.emit(ProgressEvent::TaskCompleted {
+      phase,
+      item,
+      bytes,
+      input_bytes: Some(input_bytes),
+    });
+  }
+
+  pub fn task_skipped(&self, phase: Phase, item: impl fmt::Display, reason: impl fmt::Display) {
+    let (item, reason) = (item.to_string(), reason.to_string());
+    self.emit(ProgressEvent::TaskSkipped {
+      phase,
+      item,
+      reason,
+    });
+  }
+
+  pub fn task_failed(&self, phase: Phase, item: impl fmt::Display, reason: impl fmt::Display) {
+    let (item, reason) = (item.to_string(), reason.to_string());
+    self.emit(ProgressEvent::TaskFailed {
+      phase,
+      item,
+      reason,
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Mutex;
+
+  use super::*;
+
+  #[test]
+  fn test_progress_forwards_events() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&events);
+    let progress = Progress::new(Arc::new(move |event: &ProgressEvent| {
+      recorded.lock().unwrap().push(event.clone());
+    }));
+
+    progress.phase_started(Phase::Download, Some(1));
+    progress.task_completed(Phase::Download, "a.png", 42);
+    progress.phase_finished(Phase::Download);
+
+    assert_eq!(
+      *events.lock().unwrap(),
+      vec![
+        ProgressEvent::PhaseStarted {
+          phase: Phase::Download,
+          total: Some(1)
+        },
+        ProgressEvent::TaskCompleted {
+          phase: Phase::Download,
+          item: "a.png".to_string(),
+          bytes: 42,
+          input_bytes: None
+        },
+        ProgressEvent::PhaseFinished {
+          phase: Phase::Download
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn test_progress_without_listener() {
+    Progress::default().phase_started(Phase::Convert, None);
+  }
+}
@@ -0,0 +1,228 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum TaskStatus {
+  Succeeded { output: PathBuf },
+  Skipped { reason: String },
+  Failed { reason: String },
+}
+
+/// Outcome of a single download or conversion.
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskOutcome {
+  pub item: String,
+  #[serde(flatten)]
+  pub status: TaskStatus,
+  /// Things that looked off about a task that still succeeded, e.g. a blank render.
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub warnings: Vec<String>,
+  /// Size of the input a conversion read.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub input_bytes: Option<u64>,
+  /// Size of the output a conversion wrote.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub bytes: Option<u64>,
+}
+
+/// Every task outcome of a run, in completion order.
+#[derive(Debug, Default, Serialize)]
+pub struct RunReport {
+  pub outcomes: Vec<TaskOutcome>,
+}
+
+impl RunReport {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn succeeded(&mut self, item: impl fmt::Display, output: PathBuf) {
+    self.push(item, TaskStatus::Succeeded { output });
+  }
+
+  pub fn succeeded_with_warnings(
+    &mut self,
+    item: impl fmt::Display,
+    output: PathBuf,
+    warnings: Vec<String>,
+  ) {
+    self.push(item, TaskStatus::Succeeded { output });
+    if let Some(outcome) = self.outcomes.last_mut() {
+      outcome.warnings = warnings;
+    }
+  }
+
+  /// A conversion that turned `input_bytes` into `bytes`.
+  pub fn succeeded_with_sizes(
+    &mut self,
+    item: impl fmt::Display,
+    output: PathBuf,
+    input_bytes: u64,
+    bytes: u64,
+  ) {
+    self.push(item, TaskStatus::Succeeded { output });
+    if let Some(outcome) = self.outcomes.last_mut() {
+      outcome.input_bytes = Some(input_bytes);
+      outcome.bytes = Some(bytes);
+    }
+  }
+
+  pub fn skipped(&mut self, item: impl fmt::Display, reason: impl fmt::Display) {
+    let reason = reason.to_string();
+    self.push(item, TaskStatus::Skipped { reason });
+  }
+
+  pub fn failed(&mut self, item: impl fmt::Display, reason: impl fmt::Display) {
+    let reason = reason.to_string();
+    self.push(item, TaskStatus::Failed { reason });
+  }
+
+  fn push(&mut self, item: impl fmt::Display, status: TaskStatus) {
+    self.outcomes.push(TaskOutcome {
+      item: item.to_string(),
+      status,
+      warnings: Vec::new(),
+      input_bytes: None,
+      bytes: None,
+    });
+  }
+
+  pub fn succeeded_count(&self) -> usize {
+    self.count(|status| matches!(status, TaskStatus::Succeeded { .. }))
+  }
+
+  pub fn skipped_count(&self) -> usize {
+    self.count(|status| matches!(status, TaskStatus::Skipped { .. }))
+  }
+
+  pub fn failed_count(&self) -> usize {
+    self.count(|status| matches!(status, TaskStatus::Failed { .. }))
+  }
+
+  pub fn has_failures(&self) -> bool {
+    self.failed_count() > 0
+  }
+
+  pub fn failures(&self) -> impl Iterator<Item = (&str, &str)> {
+    self
+      .outcomes
+      .iter()
+      .filter_map(|outcome| match &outcome.status {
+        TaskStatus::Failed { reason } => Some((outcome.item.as_str(), reason.as_str())),
+        _ => None,
+      })
+  }
+
+  pub fn warning_count(&self) -> usize {
+    self
+      .outcomes
+      .iter()
+      .map(|outcome| outcome.warnings.len())
+      .sum()
+  }
+
+  pub fn warnings(&self) -> impl Iterator<Item = (&str, &str)> {
+    self.outcomes.iter().flat_map(|outcome| {
+      outcome
+        .warnings
+        .iter()
+        .map(|warning| (outcome.item.as_str(), warning.as_str()))
+    })
+  }
+
+  fn count(&self, predicate: impl Fn(&TaskStatus) -> bool) -> usize {
+    self
+      .outcomes
+      .iter()
+      .filter(|outcome| predicate(&outcome.status))
+      .count()
+  }
+
+  pub fn print_summary(&self) {
+    println!("\n[📊] Summary: {}", self);
+
+    for (item, warning) in self.warnings() {
+      eprintln!("[⚠️] {}: {}", item, warning);
+    }
+
+    self.print_failures();
+  }
+
+  /// Lists the failures on stderr, where they don't mix with structured output on stdout.
+  pub fn print_failures(&self) {
+    for (item, reason) in self.failures() {
+      eprintln!("[❌] {}: {}", item, reason);
+    }
+  }
+}
+
+impl fmt::Display for RunReport {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} succeeded, {} skipped, {} failed",
+      self.succeeded_count(),
+      self.skipped_count(),
+      self.failed_count()
+    )?;
+
+    match self.warning_count() {
+      0 => Ok(()),
+      count => write!(f, " ({} warnings)", count),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_run_report_counts() {
+    let mut report = RunReport::new();
+    report.succeeded("a.png", PathBuf::from("out/a.webp"));
+    report.skipped("notes.txt", "not a PNG file");
+    report.failed("b.png", "HTTP error: 404 Not Found");
+
+    assert_eq!(report.succeeded_count(), 1);
+    assert_eq!(report.skipped_count(), 1);
+    assert_eq!(report.failed_count(), 1);
+    assert!(report.has_failures());
+    assert_eq!(
+      report.failures().collect::<Vec<_>>(),
+      vec![("b.png", "HTTP error: 404 Not Found")]
+    );
+    assert_eq!(report.to_string(), "1 succeeded, 1 skipped, 1 failed");
+  }
+
+  #[test]
+  fn test_run_report_warnings() {
+    let mut report = RunReport::new();
+    report.succeeded_with_warnings(
+      "a.png",
+      PathBuf::from("downloads/a.png"),
+      vec!["render is fully transparent".to_string()],
+    );
+
+    assert!(!report.has_failures());
+    assert_eq!(
+      report.warnings().collect::<Vec<_>>(),
+      vec![("a.png", "render is fully transparent")]
+    );
+    assert_eq!(
+      report.to_string(),
+      "1 succeeded, 0 skipped, 0 failed (1 warnings)"
+    );
+  }
+
+  #[test]
+  fn test_run_report_without_failures() {
+    let mut report = RunReport::new();
+    report.succeeded("a.png", PathBuf::from("out/a.webp"));
+
+    assert!(!report.has_failures());
+  }
+}
@@ -1,9 +1,13 @@
-use std::env;
+use std::collections::BTreeMap;
+use std::{env, fmt};
 
-use config::{Config, File as ConfigFile};
+use config::{Config, ConfigError, File as ConfigFile};
 use serde::Deserialize;
 
-#[derive(Debug, Deserialize)]
+use crate::core::converter::{CommandConverterConfig, EncoderOptions};
+use crate::utils::redact::REDACTED;
+
+#[derive(Deserialize)]
 pub struct FigmaConfig {
   pub figma_access_token: String,
   pub figma_file_key: String,
@@ -13,6 +17,22 @@ pub struct FigmaConfig {
   // pub output_folder: String,
 }
 
+// Written by hand so the access token never ends up in logs.
+impl fmt::Debug for FigmaConfig {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("FigmaConfig")
+      .field("figma_access_token", &REDACTED)
+      .field("figma_file_key", &self.figma_file_key)
+      .finish()
+  }
+}
+
+impl Default for FigmaConfig {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl FigmaConfig {
   pub fn new() -> Self {
     let config_builder = Config::builder();
@@ -21,7 +41,7 @@ impl FigmaConfig {
       .add_source(ConfigFile::with_name("config"))
       .build();
 
-    if let Some(settings) = config_result.ok() {
+    if let Ok(settings) = config_result {
       if let Ok(config) = settings.get::<FigmaConfig>("figma") {
         return config;
       }
@@ -35,3 +55,52 @@ impl FigmaConfig {
     }
   }
 }
+
+/// Reads encoder settings from the `[webp]`, `[avif]`, `[jpeg]`, `[jxl]` and `[png]` sections of
+/// the config file. Missing sections and keys keep their defaults.
+pub fn encoder_options() -> EncoderOptions {
+  let settings = read_config_file().and_then(|settings| settings.try_deserialize());
+
+  settings.unwrap_or_else(|e| {
+    tracing::warn!("Ignoring encoder settings in the config file: {}", e);
+    EncoderOptions::default()
+  })
+}
+
+/// Reads the external command converters declared in `[converters.<name>]` sections of the
+/// config file.
+pub fn command_converters() -> BTreeMap<String, CommandConverterConfig> {
+  let converters = read_config_file().and_then(|settings| settings.get("converters"));
+
+  match converters {
+    Ok(converters) => converters,
+    Err(ConfigError::NotFound(_)) => BTreeMap::new(),
+    Err(e) => {
+      tracing::warn!("Ignoring converters in the config file: {}", e);
+      BTreeMap::new()
+    }
+  }
+}
+
+fn read_config_file() -> Result<Config, ConfigError> {
+  Config::builder()
+    .add_source(ConfigFile::with_name("config").required(false))
+    .build()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_debug_redacts_access_token() {
+    let config = FigmaConfig {
+      figma_access_token: "figd_secret".to_string(),
+      figma_file_key: "file-key".to_string(),
+    };
+
+    let debug = format!("{:?}", config);
+    assert!(!debug.contains("figd_secret"));
+    assert!(debug.contains("file-key"));
+  }
+}
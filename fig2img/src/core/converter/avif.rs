@@ -1,41 +1,334 @@
+use std::fmt;
 use std::io::{Error, ErrorKind};
+use std::str::FromStr;
 
-use image::{DynamicImage, GenericImageView};
-use ravif::{Encoder, Img};
+use image::{DynamicImage, GenericImageView, Rgba};
+use rav1e::prelude::PixelRange;
+use ravif::{AlphaColorMode, BitDepth, ColorModel, EncodedImage, Encoder, Img, MatrixCoefficients};
 use rgb::FromSlice;
+use serde::{Deserialize, Serialize};
 
-pub(super) struct AvifConverter;
+use super::Converter;
 
-impl AvifConverter {
-  pub async fn convert(input_path: &str, output_path: &str) -> Result<bool, Error> {
-    let input_path = input_path.to_string();
-    let output_path = output_path.to_string();
-
-    let encoded = tokio::task::spawn_blocking(move || {
-      image::open(&input_path)
-        .map_err(|e| Error::new(ErrorKind::InvalidData, e))
-        .and_then(Self::encode_image)
+/// How colors are stored inside the AVIF file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AvifColorModel {
+  /// Best for photographic and most UI content.
+  #[default]
+  YCbCr,
+  /// Stores RGB channels as-is. Larger and less widely supported, but avoids color shifts on
+  /// saturated flat colors.
+  Rgb,
+}
+
+impl FromStr for AvifColorModel {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_ascii_lowercase().as_str() {
+      "ycbcr" => Ok(Self::YCbCr),
+      "rgb" => Ok(Self::Rgb),
+      _ => Err(format!("Unsupported AVIF color model: {}", s)),
+    }
+  }
+}
+
+impl fmt::Display for AvifColorModel {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Self::YCbCr => "ycbcr",
+      Self::Rgb => "rgb",
     })
-    .await
-    .map_err(|e| Error::new(ErrorKind::Other, e))??;
+  }
+}
+
+/// What happens to the color of transparent pixels.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AvifAlphaMode {
+  /// Replaces the invisible color of fully transparent pixels, which compresses better.
+  #[default]
+  Clean,
+  /// Keeps the color of transparent pixels untouched.
+  Dirty,
+  /// Stores color premultiplied by alpha. Needs a decoder that supports it.
+  Premultiplied,
+}
+
+impl FromStr for AvifAlphaMode {
+  type Err = String;
 
-    tokio::fs::write(output_path, encoded.as_slice()).await?;
-    Ok(true)
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_ascii_lowercase().as_str() {
+      "clean" => Ok(Self::Clean),
+      "dirty" => Ok(Self::Dirty),
+      "premultiplied" => Ok(Self::Premultiplied),
+      _ => Err(format!("Unsupported AVIF alpha mode: {}", s)),
+    }
   }
+}
+
+impl fmt::Display for AvifAlphaMode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Self::Clean => "clean",
+      Self::Dirty => "dirty",
+      Self::Premultiplied => "premultiplied",
+    })
+  }
+}
 
-  fn encode_image(img: DynamicImage) -> Result<Vec<u8>, Error> {
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AvifOptions {
+  /// 1 (smallest) to 100 (best).
+  quality: f32,
+  /// Quality of the alpha channel, 1 to 100.
+  alpha_quality: f32,
+  /// 1 (slowest, smallest files) to 10 (fastest).
+  speed: u8,
+  /// 8 or 10 bits per channel. 10 bits avoids banding in gradients, and keeps the extra
+  /// precision of 16-bit sources.
+  bit_depth: u8,
+  color_model: AvifColorModel,
+  alpha_mode: AvifAlphaMode,
+  /// Threads per image, filled in from `EncoderOptions`. `None` lets the encoder decide.
+  #[serde(skip)]
+  threads: Option<usize>,
+}
+
+impl Default for AvifOptions {
+  fn default() -> Self {
+    Self {
+      quality: 80.0,
+      alpha_quality: 80.0,
+      speed: 8,
+      bit_depth: 10,
+      color_model: AvifColorModel::default(),
+      alpha_mode: AvifAlphaMode::default(),
+      threads: None,
+    }
+  }
+}
+
+impl AvifOptions {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn quality(mut self, quality: f32) -> Self {
+    self.quality = quality;
+    self
+  }
+
+  pub fn alpha_quality(mut self, alpha_quality: f32) -> Self {
+    self.alpha_quality = alpha_quality;
+    self
+  }
+
+  pub fn speed(mut self, speed: u8) -> Self {
+    self.speed = speed;
+    self
+  }
+
+  pub fn bit_depth(mut self, bit_depth: u8) -> Self {
+    self.bit_depth = bit_depth;
+    self
+  }
+
+  pub fn color_model(mut self, color_model: AvifColorModel) -> Self {
+    self.color_model = color_model;
+    self
+  }
+
+  pub fn alpha_mode(mut self, alpha_mode: AvifAlphaMode) -> Self {
+    self.alpha_mode = alpha_mode;
+    self
+  }
+
+  pub fn threads(mut self, threads: Option<usize>) -> Self {
+    self.threads = threads;
+    self
+  }
+
+  /// The encoder panics on out-of-range values, so they are rejected up front.
+  pub fn validate(&self) -> Result<(), Error> {
+    let invalid = |message: String| Err(Error::new(ErrorKind::InvalidInput, message));
+
+    if !(1.0..=100.0).contains(&self.quality) {
+      return invalid(format!("AVIF quality must be 1-100, got {}", self.quality));
+    }
+    if !(1.0..=100.0).contains(&self.alpha_quality) {
+      return invalid(format!(
+        "AVIF alpha quality must be 1-100, got {}",
+        self.alpha_quality
+      ));
+    }
+    if !(1..=10).contains(&self.speed) {
+      return invalid(format!("AVIF speed must be 1-10, got {}", self.speed));
+    }
+    if self.bit_depth != 8 && self.bit_depth != 10 {
+      return invalid(format!(
+        "AVIF bit depth must be 8 or 10, got {}",
+        self.bit_depth
+      ));
+    }
+    Ok(())
+  }
+}
+
+pub struct AvifConverter {
+  options: AvifOptions,
+}
+
+impl AvifConverter {
+  pub fn new(options: AvifOptions) -> Self {
+    Self { options }
+  }
+
+  fn encode_image(img: &DynamicImage, options: &AvifOptions) -> Result<Vec<u8>, Error> {
+    options.validate()?;
+
+    let encoder = Encoder::new()
+      .with_quality(options.quality)
+      .with_alpha_quality(options.alpha_quality)
+      .with_speed(options.speed)
+      .with_num_threads(options.threads)
+      .with_bit_depth(match options.bit_depth {
+        8 => BitDepth::Eight,
+        _ => BitDepth::Ten,
+      })
+      .with_internal_color_model(match options.color_model {
+        AvifColorModel::YCbCr => ColorModel::YCbCr,
+        AvifColorModel::Rgb => ColorModel::RGB,
+      })
+      .with_alpha_color_mode(match options.alpha_mode {
+        AvifAlphaMode::Clean => AlphaColorMode::UnassociatedClean,
+        AvifAlphaMode::Dirty => AlphaColorMode::UnassociatedDirty,
+        AvifAlphaMode::Premultiplied => AlphaColorMode::Premultiplied,
+      });
+
+    // ravif only takes 8-bit RGBA, and its premultiplication divides instead of multiplying, so
+    // those cases go through our own planes.
+    let high_bit_depth = Self::bits_per_channel(img) > 8 && options.bit_depth > 8;
+    let encoded = match high_bit_depth || options.alpha_mode == AvifAlphaMode::Premultiplied {
+      true => Self::encode_planes(img, &encoder, options),
+      false => {
+        let (width, height) = img.dimensions();
+        let rgba = img.to_rgba8();
+        encoder.encode_rgba(Img::new(
+          rgba.as_raw().as_rgba(),
+          width as usize,
+          height as usize,
+        ))
+      }
+    };
+
+    Ok(encoded.map_err(Error::other)?.avif_file)
+  }
+
+  fn bits_per_channel(img: &DynamicImage) -> u16 {
+    let color = img.color();
+    color.bits_per_pixel() / u16::from(color.channel_count())
+  }
+
+  /// Converts the image to Y'CbCr (or GBR) planes at the output bit depth straight from 16-bit
+  /// samples, so 16-bit sources aren't truncated to 8 bits first.
+  fn encode_planes(
+    img: &DynamicImage,
+    encoder: &Encoder,
+    options: &AvifOptions,
+  ) -> Result<EncodedImage, ravif::Error> {
     let (width, height) = img.dimensions();
-    let rgba = img.to_rgba8();
-    let pixels = rgba.as_raw();
+    let (width, height) = (width as usize, height as usize);
+    let pixels = img
+      .to_rgba16()
+      .pixels()
+      .map(|&pixel| Self::apply_alpha_mode(pixel, options.alpha_mode))
+      .collect::<Vec<_>>();
+    let has_alpha = pixels.iter().any(|pixel| pixel[3] != u16::MAX);
+
+    let matrix_coefficients = match options.color_model {
+      AvifColorModel::YCbCr => MatrixCoefficients::BT601,
+      AvifColorModel::Rgb => MatrixCoefficients::Identity,
+    };
+    let max = (1u32 << options.bit_depth) - 1;
+    let planes = pixels
+      .iter()
+      .map(|&pixel| Self::to_planes(pixel, options.color_model, max));
+    let alpha = pixels.iter().map(|pixel| Self::rescale(pixel[3], max));
+
+    match options.bit_depth {
+      8 => encoder.encode_raw_planes_8_bit(
+        width,
+        height,
+        planes.map(|plane| plane.map(|v| v as u8)),
+        has_alpha.then(|| alpha.map(|v| v as u8)),
+        PixelRange::Full,
+        matrix_coefficients,
+      ),
+      _ => encoder.encode_raw_planes_10_bit(
+        width,
+        height,
+        planes,
+        has_alpha.then_some(alpha),
+        PixelRange::Full,
+        matrix_coefficients,
+      ),
+    }
+  }
+
+  fn apply_alpha_mode(Rgba([r, g, b, a]): Rgba<u16>, mode: AvifAlphaMode) -> [u16; 4] {
+    let premultiply = |v: u16| ((u32::from(v) * u32::from(a) + 32767) / 65535) as u16;
+
+    match mode {
+      AvifAlphaMode::Clean if a == 0 => [0, 0, 0, 0],
+      AvifAlphaMode::Premultiplied => [premultiply(r), premultiply(g), premultiply(b), a],
+      _ => [r, g, b, a],
+    }
+  }
+
+  /// Full-range BT.601 Y'CbCr, or GBR for the identity matrix, scaled to `0..=max`.
+  fn to_planes([r, g, b, _]: [u16; 4], color_model: AvifColorModel, max: u32) -> [u16; 3] {
+    let [r, g, b] = [r, g, b].map(|v| f32::from(v) / f32::from(u16::MAX));
+    let scale = |v: f32| (v.clamp(0.0, 1.0) * max as f32).round() as u16;
+
+    match color_model {
+      AvifColorModel::YCbCr => {
+        let y = 0.299 * r + 0.587 * g + 0.114 * b;
+        let cb = (b - y) / 1.772 + 0.5;
+        let cr = (r - y) / 1.402 + 0.5;
+        [scale(y), scale(cb), scale(cr)]
+      }
+      AvifColorModel::Rgb => [scale(g), scale(b), scale(r)],
+    }
+  }
+
+  fn rescale(value: u16, max: u32) -> u16 {
+    ((u32::from(value) * max + 32767) / 65535) as u16
+  }
+}
+
+impl Converter for AvifConverter {
+  fn name(&self) -> &str {
+    "avif"
+  }
+
+  fn extension(&self) -> &str {
+    "avif"
+  }
 
-    let encoded = Encoder::new()
-      .with_quality(80.0)
-      .with_alpha_quality(80.0)
-      .with_speed(8)
-      .encode_rgba(Img::new(pixels.as_rgba(), width as usize, height as usize))
-      .unwrap();
+  fn mime_type(&self) -> &str {
+    "image/avif"
+  }
+
+  fn validate(&self) -> Result<(), Error> {
+    self.options.validate()
+  }
 
-    Ok(encoded.avif_file)
+  fn encode(&self, image: &DynamicImage) -> Result<Vec<u8>, Error> {
+    Self::encode_image(image, &self.options)
   }
 }
 
@@ -46,14 +339,76 @@ mod tests {
   #[test]
   fn test_encode_image() {
     let img = DynamicImage::ImageRgba8(image::RgbaImage::new(100, 100));
-    let result = AvifConverter::encode_image(img);
+    let result = AvifConverter::encode_image(&img, &AvifOptions::new());
     assert!(result.is_ok());
 
     let result = result.unwrap();
-    assert!(result.len() > 0);
+    assert!(!result.is_empty());
+    assert_eq!(&result[4..8], b"ftyp");
+  }
+
+  #[test]
+  fn test_encode_image_rejects_invalid_options() {
+    let img = DynamicImage::ImageRgba8(image::RgbaImage::new(10, 10));
+    let result = AvifConverter::encode_image(&img, &AvifOptions::new().speed(11));
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+  }
+
+  #[test]
+  fn test_encode_image_with_each_mode() {
+    let mut img = image::RgbaImage::new(16, 16);
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+      *pixel = image::Rgba([x as u8 * 16, y as u8 * 16, 255, (x * 16) as u8]);
+    }
+    let img = DynamicImage::ImageRgba8(img);
+
+    for options in [
+      AvifOptions::new().bit_depth(8),
+      AvifOptions::new().color_model(AvifColorModel::Rgb),
+      AvifOptions::new().alpha_mode(AvifAlphaMode::Dirty),
+      AvifOptions::new().alpha_mode(AvifAlphaMode::Premultiplied),
+    ] {
+      let result = AvifConverter::encode_image(&img, &options).unwrap();
+      assert_eq!(&result[4..8], b"ftyp", "{:?}", options);
+    }
+  }
+
+  #[test]
+  fn test_sixteen_bit_input_keeps_precision() {
+    // Both values truncate to the same 8-bit sample but map to different 10-bit ones.
+    let dark =
+      AvifConverter::to_planes([0x1000, 0x1000, 0x1000, 0xffff], AvifColorModel::Rgb, 1023);
+    let darker =
+      AvifConverter::to_planes([0x10f0, 0x10f0, 0x10f0, 0xffff], AvifColorModel::Rgb, 1023);
+    assert_ne!(dark, darker);
+    assert_eq!(0x1000 >> 8, 0x10f0 >> 8);
+
+    let img = DynamicImage::ImageRgb16(image::ImageBuffer::from_fn(8, 8, |x, _| {
+      image::Rgb([x as u16 * 8000, 0x10f0, 0xffff])
+    }));
+    let result = AvifConverter::encode_image(&img, &AvifOptions::new()).unwrap();
     assert_eq!(&result[4..8], b"ftyp");
   }
 
+  #[test]
+  fn test_apply_alpha_mode() {
+    let pixel = Rgba([60000, 30000, 0, 32768]);
+    assert_eq!(
+      AvifConverter::apply_alpha_mode(pixel, AvifAlphaMode::Premultiplied),
+      [30000, 15000, 0, 32768]
+    );
+
+    let transparent = Rgba([100, 200, 300, 0]);
+    assert_eq!(
+      AvifConverter::apply_alpha_mode(transparent, AvifAlphaMode::Clean),
+      [0, 0, 0, 0]
+    );
+    assert_eq!(
+      AvifConverter::apply_alpha_mode(transparent, AvifAlphaMode::Dirty),
+      [100, 200, 300, 0]
+    );
+  }
+
   // TODO: Uncomment and fix the async test when the conversion issue is resolved
   // #[tokio::test]
   // async fn test_avif_conversion() { ... }
@@ -0,0 +1,388 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::process::Command;
+
+use image::{DynamicImage, ImageFormat};
+use serde::{Deserialize, Serialize};
+
+use super::{Converter, SourceImage};
+
+/// A command line, either as one string split on whitespace or as separate arguments for values
+/// that contain spaces. No shell is involved.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum CommandTemplate {
+  Line(String),
+  Args(Vec<String>),
+}
+
+impl CommandTemplate {
+  fn args(&self) -> Vec<&str> {
+    match self {
+      Self::Line(line) => line.split_whitespace().collect(),
+      Self::Args(args) => args.iter().map(String::as_str).collect(),
+    }
+  }
+}
+
+/// A converter declared in the `[converters.<name>]` section of the config file, e.g.
+///
+/// ```toml
+/// [converters.jxl]
+/// command = "cjxl {input} {output} --distance {distance}"
+/// extension = "jxl"
+/// mime_type = "image/jxl"
+/// version_command = "cjxl --version"
+/// min_version = "0.10"
+/// options = { distance = "1.0" }
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CommandConverterConfig {
+  /// `{input}` and `{output}` are replaced with the file paths, and `{<option>}` with the value
+  /// of that option.
+  command: CommandTemplate,
+  extension: String,
+  #[serde(default)]
+  mime_type: Option<String>,
+  /// Run once before converting, to check the tool is installed.
+  #[serde(default)]
+  version_command: Option<CommandTemplate>,
+  /// Lowest accepted version, compared with the first version number `version_command` prints.
+  #[serde(default)]
+  min_version: Option<String>,
+  /// Default values for the option placeholders.
+  #[serde(default)]
+  options: BTreeMap<String, String>,
+}
+
+impl CommandConverterConfig {
+  pub fn new(command: CommandTemplate, extension: impl Into<String>) -> Self {
+    Self {
+      command,
+      extension: extension.into(),
+      mime_type: None,
+      version_command: None,
+      min_version: None,
+      options: BTreeMap::new(),
+    }
+  }
+
+  pub fn mime_type(mut self, mime_type: impl Into<String>) -> Self {
+    self.mime_type = Some(mime_type.into());
+    self
+  }
+
+  pub fn version_command(mut self, version_command: CommandTemplate) -> Self {
+    self.version_command = Some(version_command);
+    self
+  }
+
+  pub fn min_version(mut self, min_version: impl Into<String>) -> Self {
+    self.min_version = Some(min_version.into());
+    self
+  }
+
+  pub fn option(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+    self.options.insert(key.into(), value.into());
+    self
+  }
+}
+
+/// Runs an external tool to produce each file.
+pub struct CommandConverter {
+  name: String,
+  config: CommandConverterConfig,
+}
+
+impl CommandConverter {
+  pub fn new(name: impl Into<String>, config: CommandConverterConfig) -> Self {
+    Self {
+      name: name.into(),
+      config,
+    }
+  }
+
+  /// Overrides option values, e.g. from the command line.
+  pub fn with_options(mut self, options: &BTreeMap<String, String>) -> Self {
+    self.config.options.extend(options.clone());
+    self
+  }
+
+  fn expand(&self, arg: &str, input_path: &Path, output_path: &Path) -> Result<String, Error> {
+    let mut expanded = String::new();
+    let mut rest = arg;
+
+    while let Some(start) = rest.find('{') {
+      let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+        break;
+      };
+      let key = &rest[start + 1..end];
+      let value = match key {
+        "input" => input_path.to_string_lossy().into_owned(),
+        "output" => output_path.to_string_lossy().into_owned(),
+        _ => self.config.options.get(key).cloned().ok_or_else(|| {
+          Error::new(
+            ErrorKind::InvalidInput,
+            format!("{}: no value for placeholder {{{}}}", self.name, key),
+          )
+        })?,
+      };
+
+      expanded.push_str(&rest[..start]);
+      expanded.push_str(&value);
+      rest = &rest[end + 1..];
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+  }
+
+  pub(super) fn command(
+    &self,
+    template: &CommandTemplate,
+    input_path: &Path,
+    output_path: &Path,
+  ) -> Result<Command, Error> {
+    let args = template
+      .args()
+      .into_iter()
+      .map(|arg| self.expand(arg, input_path, output_path))
+      .collect::<Result<Vec<_>, _>>()?;
+
+    let Some((program, args)) = args.split_first() else {
+      return Err(Error::new(
+        ErrorKind::InvalidInput,
+        format!("{}: command is empty", self.name),
+      ));
+    };
+
+    let mut command = Command::new(program);
+    command.args(args);
+    Ok(command)
+  }
+
+  fn run(&self, mut command: Command) -> Result<String, Error> {
+    let output = command.output().map_err(|e| {
+      Error::new(
+        e.kind(),
+        format!(
+          "{}: failed to run {:?}: {}",
+          self.name,
+          command.get_program(),
+          e
+        ),
+      )
+    })?;
+
+    match output.status.success() {
+      true => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+      false => Err(Error::other(format!(
+        "{}: {:?} exited with {}: {}",
+        self.name,
+        command.get_program(),
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim()
+      ))),
+    }
+  }
+
+  fn check_version(&self, version_command: &CommandTemplate) -> Result<(), Error> {
+    let command = self.command(version_command, Path::new(""), Path::new(""))?;
+    let output = self.run(command)?;
+
+    let Some(min_version) = &self.config.min_version else {
+      return Ok(());
+    };
+    let version = parse_version(&output).ok_or_else(|| {
+      Error::other(format!(
+        "{}: no version number in {:?}",
+        self.name,
+        output.trim()
+      ))
+    })?;
+    let min = parse_version(min_version).unwrap_or_default();
+
+    match version >= min {
+      true => Ok(()),
+      false => Err(Error::other(format!(
+        "{}: version {} is older than the required {}",
+        self.name,
+        join_version(&version),
+        min_version
+      ))),
+    }
+  }
+}
+
+impl Converter for CommandConverter {
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn extension(&self) -> &str {
+    &self.config.extension
+  }
+
+  fn mime_type(&self) -> &str {
+    self
+      .config
+      .mime_type
+      .as_deref()
+      .unwrap_or("application/octet-stream")
+  }
+
+  /// Checks every placeholder has a value and, when configured, that the tool is recent enough.
+  fn validate(&self) -> Result<(), Error> {
+    self.command(&self.config.command, Path::new(""), Path::new(""))?;
+
+    match &self.config.version_command {
+      Some(version_command) => self.check_version(version_command),
+      None => Ok(()),
+    }
+  }
+
+  fn encode(&self, image: &DynamicImage) -> Result<Vec<u8>, Error> {
+    let temp_dir = super::temp_dir()?;
+    let input_path = temp_dir.path().join("input.png");
+    let output_path = temp_dir
+      .path()
+      .join(format!("output.{}", self.config.extension));
+
+    image
+      .save_with_format(&input_path, ImageFormat::Png)
+      .map_err(Error::other)?;
+    self.convert(&input_path, &output_path)?;
+    std::fs::read(&output_path)
+  }
+
+  fn convert_source(&self, source: &SourceImage, output_path: &Path) -> Result<(), Error> {
+    let Some(file) = source.file() else {
+      return std::fs::write(output_path, self.encode(source.image()?)?);
+    };
+    let command = self.command(&self.config.command, file, output_path)?;
+    self.run(command)?;
+
+    match output_path.exists() {
+      true => Ok(()),
+      false => Err(Error::other(format!(
+        "{}: command succeeded but wrote no {}",
+        self.name,
+        output_path.display()
+      ))),
+    }
+  }
+}
+
+/// The first dotted number in `text`, e.g. `[0, 11, 1]` for `cjxl v0.11.1 [AVX2]`.
+fn parse_version(text: &str) -> Option<Vec<u64>> {
+  let start = text.find(|c: char| c.is_ascii_digit())?;
+  let version = text[start..]
+    .split(|c: char| !c.is_ascii_digit() && c != '.')
+    .next()?;
+
+  version
+    .split('.')
+    .filter(|part| !part.is_empty())
+    .map(|part| part.parse().ok())
+    .collect()
+}
+
+fn join_version(version: &[u64]) -> String {
+  version
+    .iter()
+    .map(u64::to_string)
+    .collect::<Vec<_>>()
+    .join(".")
+}
+
+#[cfg(test)]
+mod tests {
+  use tempfile::tempdir;
+
+  use super::*;
+
+  fn sh(script: &str) -> CommandTemplate {
+    CommandTemplate::Args(vec!["sh".into(), "-c".into(), script.into()])
+  }
+
+  #[test]
+  fn test_parse_version() {
+    assert_eq!(parse_version("cjxl v0.11.1 [AVX2]"), Some(vec![0, 11, 1]));
+    assert_eq!(parse_version("avifenc 1.0"), Some(vec![1, 0]));
+    assert_eq!(parse_version("no digits"), None);
+  }
+
+  #[test]
+  fn test_convert_expands_placeholders() {
+    let dir = tempdir().unwrap();
+    let input_path = dir.path().join("in.png");
+    let output_path = dir.path().join("out.txt");
+    // Only PNG files are handed to commands as they are.
+    std::fs::write(&input_path, b"\x89PNG\r\n\x1a\n").unwrap();
+
+    let command = ["sh", "-c", r#"cat "$0" > "$1" && echo " q=$2" >> "$1""#]
+      .into_iter()
+      .chain(["{input}", "{output}", "{quality}"])
+      .map(String::from)
+      .collect();
+    let config =
+      CommandConverterConfig::new(CommandTemplate::Args(command), "txt").option("quality", "80");
+
+    let overrides = BTreeMap::from([("quality".to_string(), "95".to_string())]);
+    let converter = CommandConverter::new("text", config).with_options(&overrides);
+    converter.convert(&input_path, &output_path).unwrap();
+
+    assert_eq!(
+      std::fs::read(&output_path).unwrap(),
+      b"\x89PNG\r\n\x1a\n q=95\n"
+    );
+  }
+
+  #[test]
+  fn test_validate() {
+    let missing_option = CommandConverterConfig::new(
+      CommandTemplate::Line("cjxl {input} -d {distance}".into()),
+      "jxl",
+    );
+    let error = CommandConverter::new("jxl", missing_option)
+      .validate()
+      .unwrap_err();
+    assert!(error.to_string().contains("{distance}"), "{}", error);
+
+    let config = |min_version: &str| {
+      CommandConverterConfig::new(CommandTemplate::Line("true".into()), "jxl")
+        .version_command(sh("echo cjxl v0.11.1"))
+        .min_version(min_version)
+    };
+    assert!(CommandConverter::new("jxl", config("0.10"))
+      .validate()
+      .is_ok());
+    assert!(CommandConverter::new("jxl", config("0.12"))
+      .validate()
+      .is_err());
+
+    let not_installed = CommandConverterConfig::new(CommandTemplate::Line("true".into()), "x")
+      .version_command(CommandTemplate::Line(
+        "fig2img-missing-tool --version".into(),
+      ));
+    assert!(CommandConverter::new("x", not_installed)
+      .validate()
+      .is_err());
+  }
+
+  #[test]
+  fn test_deserialize_config() {
+    let config: CommandConverterConfig = toml::from_str(
+      r#"
+        command = ["squoosh-cli", "--mozjpeg", "{quality}", "-d", "{output}", "{input}"]
+        extension = "jpg"
+        options = { quality = "75" }
+      "#,
+    )
+    .unwrap();
+
+    assert_eq!(config.command.args()[0], "squoosh-cli");
+    assert_eq!(config.options["quality"], "75");
+  }
+}
@@ -0,0 +1,347 @@
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+
+use image::{DynamicImage, RgbImage};
+use mozjpeg::{ColorSpace, Compress};
+use serde::{Deserialize, Serialize};
+
+use super::Converter;
+
+/// Resolution of the color channels relative to brightness.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ChromaSubsampling {
+  /// Full color resolution. Keeps thin colored text and lines crisp.
+  #[serde(rename = "444")]
+  Yuv444,
+  /// Half horizontal color resolution.
+  #[serde(rename = "422")]
+  Yuv422,
+  /// Half horizontal and vertical color resolution. Smallest, and fine for photos.
+  #[default]
+  #[serde(rename = "420")]
+  Yuv420,
+}
+
+impl ChromaSubsampling {
+  /// Size of a chroma sample in luma pixels, horizontally and vertically.
+  fn pixel_size(self) -> (u8, u8) {
+    match self {
+      Self::Yuv444 => (1, 1),
+      Self::Yuv422 => (2, 1),
+      Self::Yuv420 => (2, 2),
+    }
+  }
+}
+
+impl FromStr for ChromaSubsampling {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.replace(':', "").as_str() {
+      "444" => Ok(Self::Yuv444),
+      "422" => Ok(Self::Yuv422),
+      "420" => Ok(Self::Yuv420),
+      _ => Err(format!("Unsupported chroma subsampling: {}", s)),
+    }
+  }
+}
+
+impl fmt::Display for ChromaSubsampling {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Self::Yuv444 => "444",
+      Self::Yuv422 => "422",
+      Self::Yuv420 => "420",
+    })
+  }
+}
+
+/// An opaque color, written as `#rrggbb` (the `#` is optional).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Color([u8; 3]);
+
+impl Color {
+  pub const WHITE: Self = Self([255, 255, 255]);
+
+  pub fn new(r: u8, g: u8, b: u8) -> Self {
+    Self([r, g, b])
+  }
+}
+
+impl FromStr for Color {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    let channel = |i: usize| {
+      hex
+        .get(i..i + 2)
+        .and_then(|c| u8::from_str_radix(c, 16).ok())
+    };
+
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+      (6, Some(r), Some(g), Some(b)) => Ok(Self([r, g, b])),
+      _ => Err(format!("{} is not a #rrggbb color", s)),
+    }
+  }
+}
+
+impl TryFrom<String> for Color {
+  type Error = String;
+
+  fn try_from(value: String) -> Result<Self, Self::Error> {
+    value.parse()
+  }
+}
+
+impl From<Color> for String {
+  fn from(color: Color) -> Self {
+    color.to_string()
+  }
+}
+
+impl fmt::Display for Color {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let [r, g, b] = self.0;
+    write!(f, "#{:02x}{:02x}{:02x}", r, g, b)
+  }
+}
+
+/// Defaults match mozjpeg's `cjpeg`.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct JpegOptions {
+  /// 1 (smallest) to 100 (best).
+  quality: f32,
+  /// Renders a blurry preview first while loading. Usually also a little smaller.
+  progressive: bool,
+  chroma_subsampling: ChromaSubsampling,
+  /// mozjpeg's trellis quantization and scan optimization: smaller files for slower encoding.
+  /// Off gives libjpeg-turbo compatible output.
+  trellis: bool,
+  /// JPEG has no transparency, so transparent pixels are blended onto this color.
+  background: Color,
+}
+
+impl Default for JpegOptions {
+  fn default() -> Self {
+    Self {
+      quality: 75.0,
+      progressive: true,
+      chroma_subsampling: ChromaSubsampling::default(),
+      trellis: true,
+      background: Color::WHITE,
+    }
+  }
+}
+
+impl JpegOptions {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn quality(mut self, quality: f32) -> Self {
+    self.quality = quality;
+    self
+  }
+
+  pub fn progressive(mut self, progressive: bool) -> Self {
+    self.progressive = progressive;
+    self
+  }
+
+  pub fn chroma_subsampling(mut self, chroma_subsampling: ChromaSubsampling) -> Self {
+    self.chroma_subsampling = chroma_subsampling;
+    self
+  }
+
+  pub fn trellis(mut self, trellis: bool) -> Self {
+    self.trellis = trellis;
+    self
+  }
+
+  pub fn background(mut self, background: Color) -> Self {
+    self.background = background;
+    self
+  }
+
+  pub fn validate(&self) -> Result<(), Error> {
+    if !(1.0..=100.0).contains(&self.quality) {
+      return Err(Error::new(
+        ErrorKind::InvalidInput,
+        format!("JPEG quality must be 1-100, got {}", self.quality),
+      ));
+    }
+    Ok(())
+  }
+}
+
+pub struct JpegConverter {
+  options: JpegOptions,
+}
+
+impl JpegConverter {
+  pub fn new(options: JpegOptions) -> Self {
+    Self { options }
+  }
+}
+
+impl Converter for JpegConverter {
+  fn name(&self) -> &str {
+    "jpeg"
+  }
+
+  fn extension(&self) -> &str {
+    "jpg"
+  }
+
+  fn mime_type(&self) -> &str {
+    "image/jpeg"
+  }
+
+  fn validate(&self) -> Result<(), Error> {
+    self.options.validate()
+  }
+
+  fn encode(&self, image: &DynamicImage) -> Result<Vec<u8>, Error> {
+    let rgb = flatten(image, self.options.background);
+    let options = self.options;
+
+    // libjpeg reports errors by unwinding out of the encoder.
+    panic::catch_unwind(AssertUnwindSafe(|| {
+      let mut compress = Compress::new(ColorSpace::JCS_RGB);
+      if !options.trellis {
+        // Resets every setting, so it has to come first.
+        compress.set_fastest_defaults();
+        compress.set_optimize_coding(true);
+      }
+      compress.set_size(rgb.width() as usize, rgb.height() as usize);
+      compress.set_quality(options.quality);
+      let pixel_size = options.chroma_subsampling.pixel_size();
+      compress.set_chroma_sampling_pixel_sizes(pixel_size, pixel_size);
+      if options.progressive {
+        compress.set_progressive_mode();
+      } else {
+        compress.set_optimize_scans(false);
+      }
+
+      let mut started = compress.start_compress(Vec::new())?;
+      started.write_scanlines(rgb.as_raw())?;
+      started.finish()
+    }))
+    .map_err(|_| Error::other("JPEG encoding failed"))?
+  }
+}
+
+/// Blends `image` onto an opaque `background`.
+fn flatten(image: &DynamicImage, background: Color) -> RgbImage {
+  if !image.color().has_alpha() {
+    return image.to_rgb8();
+  }
+
+  let rgba = image.to_rgba8();
+  RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+    let [r, g, b, a] = rgba.get_pixel(x, y).0;
+    let blend = |color: u8, background: u8| {
+      let (color, background, alpha) = (u32::from(color), u32::from(background), u32::from(a));
+      ((color * alpha + background * (255 - alpha) + 127) / 255) as u8
+    };
+    image::Rgb([
+      blend(r, background.0[0]),
+      blend(g, background.0[1]),
+      blend(b, background.0[2]),
+    ])
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use image::{ImageFormat, Rgba, RgbaImage};
+
+  use super::*;
+
+  fn gradient() -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 48, |x, y| {
+      Rgba([x as u8 * 4, y as u8 * 5, 128, 255])
+    }))
+  }
+
+  fn is_progressive(jpeg: &[u8]) -> bool {
+    // SOF2 marks a progressive frame, SOF0/SOF1 a baseline one.
+    jpeg.windows(2).any(|marker| marker == [0xFF, 0xC2])
+  }
+
+  #[test]
+  fn test_jpeg_encoding() {
+    for options in [
+      JpegOptions::new(),
+      JpegOptions::new().progressive(false),
+      JpegOptions::new().trellis(false),
+      JpegOptions::new().chroma_subsampling(ChromaSubsampling::Yuv444),
+    ] {
+      let encoded = JpegConverter::new(options).encode(&gradient()).unwrap();
+
+      assert_eq!(image::guess_format(&encoded).unwrap(), ImageFormat::Jpeg);
+      assert_eq!(
+        is_progressive(&encoded),
+        options.progressive,
+        "{:?}",
+        options
+      );
+
+      let decoded = image::load_from_memory(&encoded).unwrap().to_rgb8();
+      assert_eq!(decoded.dimensions(), (64, 48));
+      let [r, g, b] = decoded.get_pixel(32, 24).0;
+      assert!(r.abs_diff(128) < 12 && g.abs_diff(120) < 12 && b.abs_diff(128) < 12);
+    }
+  }
+
+  #[test]
+  fn test_transparency_is_flattened_onto_background() {
+    let mut img = RgbaImage::from_pixel(16, 16, Rgba([0, 0, 0, 0]));
+    img.put_pixel(0, 0, Rgba([0, 0, 255, 128]));
+    assert_eq!(
+      flatten(
+        &DynamicImage::ImageRgba8(img.clone()),
+        Color::new(255, 0, 0)
+      )
+      .get_pixel(0, 0)
+      .0,
+      [127, 0, 128]
+    );
+
+    let options = JpegOptions::new()
+      .background(Color::new(255, 0, 0))
+      .chroma_subsampling(ChromaSubsampling::Yuv444);
+    let encoded = JpegConverter::new(options)
+      .encode(&DynamicImage::ImageRgba8(img))
+      .unwrap();
+
+    let [r, g, b] = image::load_from_memory(&encoded)
+      .unwrap()
+      .to_rgb8()
+      .get_pixel(8, 8)
+      .0;
+    assert!(r > 240 && g < 16 && b < 16, "{:?}", [r, g, b]);
+  }
+
+  #[test]
+  fn test_parse_options() {
+    assert_eq!("#1a2B3c".parse::<Color>(), Ok(Color::new(0x1a, 0x2b, 0x3c)));
+    assert_eq!("ffffff".parse::<Color>(), Ok(Color::WHITE));
+    assert!("#fff".parse::<Color>().is_err());
+    assert_eq!(Color::new(1, 2, 255).to_string(), "#0102ff");
+
+    assert_eq!(
+      "4:4:4".parse::<ChromaSubsampling>(),
+      Ok(ChromaSubsampling::Yuv444)
+    );
+    assert!("411".parse::<ChromaSubsampling>().is_err());
+
+    assert!(JpegOptions::new().validate().is_ok());
+    assert!(JpegOptions::new().quality(0.0).validate().is_err());
+  }
+}
@@ -0,0 +1,247 @@
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+use super::{CommandConverter, CommandConverterConfig, CommandTemplate, Converter, SourceImage};
+
+const CJXL: &str = "cjxl";
+const CJXL_ARGS: &str = "{input} {output} --distance {distance} --effort {effort}";
+
+/// Defaults match `cjxl`'s.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct JxlOptions {
+  /// Visual distance from the original: 0 is lossless, 1 visually lossless, up to 25.
+  distance: f32,
+  /// Encoding effort, 1 (fastest) to 10 (smallest files).
+  effort: u8,
+  /// Same as a distance of 0.
+  lossless: bool,
+  /// Threads per image, filled in from `EncoderOptions`. `None` lets the encoder decide.
+  #[serde(skip)]
+  threads: Option<usize>,
+}
+
+impl Default for JxlOptions {
+  fn default() -> Self {
+    Self {
+      distance: 1.0,
+      effort: 7,
+      lossless: false,
+      threads: None,
+    }
+  }
+}
+
+impl JxlOptions {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn distance(mut self, distance: f32) -> Self {
+    self.distance = distance;
+    self
+  }
+
+  pub fn effort(mut self, effort: u8) -> Self {
+    self.effort = effort;
+    self
+  }
+
+  pub fn lossless(mut self, lossless: bool) -> Self {
+    self.lossless = lossless;
+    self
+  }
+
+  pub fn threads(mut self, threads: Option<usize>) -> Self {
+    self.threads = threads;
+    self
+  }
+
+  pub fn validate(&self) -> Result<(), Error> {
+    let invalid = |message: String| Err(Error::new(ErrorKind::InvalidInput, message));
+
+    if !(0.0..=25.0).contains(&self.distance) {
+      return invalid(format!(
+        "JPEG XL distance must be 0-25, got {}",
+        self.distance
+      ));
+    }
+    if !(1..=10).contains(&self.effort) {
+      return invalid(format!("JPEG XL effort must be 1-10, got {}", self.effort));
+    }
+    Ok(())
+  }
+
+  fn command_template(&self, program: &str) -> CommandTemplate {
+    CommandTemplate::Line(match self.threads {
+      Some(_) => format!("{} {} --num_threads {{threads}}", program, CJXL_ARGS),
+      None => format!("{} {}", program, CJXL_ARGS),
+    })
+  }
+
+  fn command_config(&self, program: &str) -> CommandConverterConfig {
+    let distance = match self.lossless {
+      true => 0.0,
+      false => self.distance,
+    };
+
+    CommandConverterConfig::new(self.command_template(program), "jxl")
+      .mime_type("image/jxl")
+      .version_command(CommandTemplate::Line(format!("{} --version", program)))
+      .option("distance", distance.to_string())
+      .option("effort", self.effort.to_string())
+      .option("threads", self.threads.unwrap_or_default().to_string())
+  }
+}
+
+/// Encodes with `cjxl` from libjxl, which must be on the `PATH`. The Rust bindings (`jpegxl-rs`)
+/// would build libjxl from source, which needs CMake and a C++ toolchain instead.
+pub struct JxlConverter {
+  options: JxlOptions,
+  command: CommandConverter,
+}
+
+impl JxlConverter {
+  pub fn new(options: JxlOptions) -> Self {
+    Self::with_program(options, CJXL)
+  }
+
+  fn with_program(options: JxlOptions, program: &str) -> Self {
+    Self {
+      options,
+      command: CommandConverter::new("jxl", options.command_config(program)),
+    }
+  }
+}
+
+impl Converter for JxlConverter {
+  fn name(&self) -> &str {
+    "jxl"
+  }
+
+  fn extension(&self) -> &str {
+    "jxl"
+  }
+
+  fn mime_type(&self) -> &str {
+    "image/jxl"
+  }
+
+  fn validate(&self) -> Result<(), Error> {
+    self.options.validate()?;
+    self.command.validate().map_err(|e| match e.kind() {
+      ErrorKind::NotFound => Error::new(
+        ErrorKind::NotFound,
+        "cjxl is not installed. Install libjxl (`brew install jpeg-xl`, `apt install libjxl-tools`)",
+      ),
+      _ => e,
+    })
+  }
+
+  fn encode(&self, image: &DynamicImage) -> Result<Vec<u8>, Error> {
+    self.command.encode(image)
+  }
+
+  // cjxl reads PNGs itself and keeps their bit depth and color profile.
+  fn convert_source(&self, source: &SourceImage, output_path: &Path) -> Result<(), Error> {
+    self.command.convert_source(source, output_path)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::process::Command;
+
+  use image::{Rgba, RgbaImage};
+
+  use super::*;
+
+  fn is_installed(program: &str) -> bool {
+    Command::new(program).arg("--version").output().is_ok()
+  }
+
+  fn args(options: JxlOptions) -> Vec<String> {
+    let command = CommandConverter::new("jxl", options.command_config(CJXL));
+    let command = command
+      .command(
+        &options.command_template(CJXL),
+        Path::new("in.png"),
+        Path::new("out.jxl"),
+      )
+      .unwrap();
+    command
+      .get_args()
+      .map(|arg| arg.to_string_lossy().into_owned())
+      .collect()
+  }
+
+  #[test]
+  fn test_cjxl_arguments() {
+    assert_eq!(
+      args(JxlOptions::new()),
+      ["in.png", "out.jxl", "--distance", "1", "--effort", "7"]
+    );
+    assert_eq!(
+      args(JxlOptions::new().distance(2.5).effort(9).lossless(true)),
+      ["in.png", "out.jxl", "--distance", "0", "--effort", "9"]
+    );
+    assert_eq!(
+      args(JxlOptions::new().threads(Some(2))),
+      [
+        "in.png",
+        "out.jxl",
+        "--distance",
+        "1",
+        "--effort",
+        "7",
+        "--num_threads",
+        "2"
+      ]
+    );
+  }
+
+  #[test]
+  fn test_validate_rejects_out_of_range_options() {
+    assert!(JxlOptions::new().validate().is_ok());
+    assert!(JxlOptions::new().distance(26.0).validate().is_err());
+    assert!(JxlOptions::new().effort(0).validate().is_err());
+  }
+
+  #[test]
+  fn test_fails_when_cjxl_is_not_installed() {
+    let converter = JxlConverter::with_program(JxlOptions::new(), "fig2img-test-missing-cjxl");
+
+    let error = converter.validate().unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    assert!(error.to_string().contains("Install libjxl"), "{}", error);
+  }
+
+  #[test]
+  fn test_encode_with_cjxl() {
+    // Only runs where libjxl is installed.
+    if !is_installed(CJXL) {
+      return;
+    }
+    let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(6, 4, Rgba([0, 128, 255, 128])));
+
+    let encoded = JxlConverter::new(JxlOptions::new()).encode(&image).unwrap();
+    let codestream = encoded.starts_with(&[0xff, 0x0a]);
+    let container = encoded.starts_with(b"\0\0\0\x0cJXL ");
+    assert!(codestream || container, "not a JPEG XL file");
+
+    if is_installed("djxl") {
+      let dir = tempfile::tempdir().unwrap();
+      let (jxl_path, png_path) = (dir.path().join("out.jxl"), dir.path().join("out.png"));
+      std::fs::write(&jxl_path, &encoded).unwrap();
+      let status = Command::new("djxl")
+        .args([&jxl_path, &png_path])
+        .status()
+        .unwrap();
+      assert!(status.success());
+      assert_eq!(image::image_dimensions(&png_path).unwrap(), (6, 4));
+    }
+  }
+}
@@ -1,16 +1,223 @@
 mod avif;
+mod command;
+mod jpeg;
+mod jxl;
+mod png;
+mod registry;
 mod webp;
 
-use std::io::Error;
+use std::cell::OnceCell;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read};
+use std::path::Path;
 
-pub struct ImageConverter;
+pub use avif::{AvifAlphaMode, AvifColorModel, AvifConverter, AvifOptions};
+pub use command::{CommandConverter, CommandConverterConfig, CommandTemplate};
+use image::{DynamicImage, ImageDecoder, ImageReader};
+pub use jpeg::{ChromaSubsampling, Color, JpegConverter, JpegOptions};
+pub use jxl::{JxlConverter, JxlOptions};
+pub use png::{PngConverter, PngOptions};
+pub use registry::ConverterRegistry;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+pub use webp::{WebPBackend, WebPConverter, WebPOptions};
 
-impl ImageConverter {
-  pub async fn convert_to_webp(input_path: &str, output_path: &str) -> Result<bool, Error> {
-    webp::WebPConverter::convert(input_path, output_path).await
+use crate::core::svg::{self, SvgRasterizer};
+
+/// An output format. Implementations carry their own encoder settings and are looked up by
+/// [`name`](Self::name) in a [`ConverterRegistry`].
+///
+/// Methods are blocking; callers run them off the async runtime.
+pub trait Converter: Send + Sync {
+  /// Name used to select the converter, e.g. `webp` in `--format webp`.
+  fn name(&self) -> &str;
+
+  /// Extension of the files it writes, without the dot.
+  fn extension(&self) -> &str;
+
+  fn mime_type(&self) -> &str;
+
+  /// Checks the encoder settings, so bad ones are reported once instead of for every file.
+  fn validate(&self) -> Result<(), Error> {
+    Ok(())
+  }
+
+  fn encode(&self, image: &DynamicImage) -> Result<Vec<u8>, Error>;
+
+  /// Converts `source` into `output_path`. Converters that can read the input file directly may
+  /// override this to skip decoding.
+  fn convert_source(&self, source: &SourceImage, output_path: &Path) -> Result<(), Error> {
+    let encoded = self.encode(source.image()?)?;
+    std::fs::write(output_path, encoded)
+  }
+
+  fn convert(&self, input_path: &Path, output_path: &Path) -> Result<(), Error> {
+    self.convert_source(&SourceImage::new(input_path), output_path)
+  }
+}
+
+/// An input file that is decoded on first use, so converting it to several formats decodes it
+/// once.
+pub struct SourceImage<'a> {
+  path: &'a Path,
+  image: OnceCell<DynamicImage>,
+  modified: bool,
+  is_png: OnceCell<bool>,
+  svg: Option<&'a SvgRasterizer>,
+}
+
+impl<'a> SourceImage<'a> {
+  pub fn new(path: &'a Path) -> Self {
+    Self {
+      path,
+      image: OnceCell::new(),
+      modified: false,
+      is_png: OnceCell::new(),
+      svg: None,
+    }
+  }
+
+  /// An image made from the file at `path`, e.g. by resizing it.
+  pub fn modified(path: &'a Path, image: DynamicImage) -> Self {
+    Self {
+      path,
+      image: OnceCell::from(image),
+      modified: true,
+      is_png: OnceCell::new(),
+      svg: None,
+    }
+  }
+
+  /// Rasterizes the file with `svg` if it is an SVG document.
+  pub fn with_svg(mut self, svg: Option<&'a SvgRasterizer>) -> Self {
+    self.svg = svg;
+    self
+  }
+
+  /// The PNG file holding exactly this image, for converters that read files directly. `None`
+  /// for modified images and other input formats, which such converters have to write out as
+  /// PNG first; that also applies the EXIF orientation external tools might ignore.
+  pub fn file(&self) -> Option<&Path> {
+    let is_png = *self.is_png.get_or_init(|| is_png(self.path));
+    (!self.modified && is_png).then_some(self.path)
+  }
+
+  pub fn image(&self) -> Result<&DynamicImage, Error> {
+    if let Some(image) = self.image.get() {
+      return Ok(image);
+    }
+    let image = match self.svg {
+      Some(svg) if svg::is_svg_file(self.path) => svg.rasterize(self.path)?,
+      _ => decode(self.path)?,
+    };
+    Ok(self.image.get_or_init(|| image))
+  }
+}
+
+/// Decodes an image, detecting its format from the content rather than the extension, and
+/// rotates or flips it as its EXIF orientation says.
+pub fn decode(path: &Path) -> Result<DynamicImage, Error> {
+  let invalid = |e: image::ImageError| Error::new(ErrorKind::InvalidData, e);
+
+  let mut decoder = ImageReader::open(path)?
+    .with_guessed_format()?
+    .into_decoder()
+    .map_err(invalid)?;
+  let orientation = decoder.orientation().map_err(invalid)?;
+  let mut image = DynamicImage::from_decoder(decoder).map_err(invalid)?;
+  image.apply_orientation(orientation);
+  Ok(image)
+}
+
+fn is_png(path: &Path) -> bool {
+  let mut signature = [0; 8];
+  File::open(path)
+    .and_then(|mut file| file.read_exact(&mut signature))
+    .is_ok_and(|()| signature == *b"\x89PNG\r\n\x1a\n")
+}
+
+/// A new directory in the system temp directory for handing files to external encoders. Its name
+/// is random and only the current user can enter it, so nobody else can plant files or links where
+/// the encoder writes. It is removed when dropped.
+fn temp_dir() -> Result<TempDir, Error> {
+  tempfile::Builder::new().prefix("fig2img-").tempdir()
+}
+
+/// Encoder settings for the built-in formats.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+pub struct EncoderOptions {
+  #[serde(default)]
+  webp: WebPOptions,
+  #[serde(default)]
+  avif: AvifOptions,
+  #[serde(default)]
+  jpeg: JpegOptions,
+  #[serde(default)]
+  jxl: JxlOptions,
+  #[serde(default)]
+  png: PngOptions,
+  /// Threads each image may be encoded with. `None` (or 0) lets every encoder decide, which may
+  /// use all CPUs per image.
+  #[serde(default)]
+  threads: Option<usize>,
+}
+
+impl EncoderOptions {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn webp(mut self, webp: WebPOptions) -> Self {
+    self.webp = webp;
+    self
+  }
+
+  pub fn avif(mut self, avif: AvifOptions) -> Self {
+    self.avif = avif;
+    self
+  }
+
+  pub fn jpeg(mut self, jpeg: JpegOptions) -> Self {
+    self.jpeg = jpeg;
+    self
+  }
+
+  pub fn jxl(mut self, jxl: JxlOptions) -> Self {
+    self.jxl = jxl;
+    self
+  }
+
+  pub fn png(mut self, png: PngOptions) -> Self {
+    self.png = png;
+    self
+  }
+
+  pub fn threads(mut self, threads: Option<usize>) -> Self {
+    self.threads = threads;
+    self
+  }
+
+  pub fn threads_per_image(&self) -> Option<usize> {
+    self.threads.filter(|&threads| threads > 0)
+  }
+
+  pub fn webp_options(&self) -> WebPOptions {
+    self.webp.threads(self.threads_per_image())
+  }
+
+  pub fn avif_options(&self) -> AvifOptions {
+    self.avif.threads(self.threads_per_image())
+  }
+
+  pub fn jpeg_options(&self) -> JpegOptions {
+    self.jpeg
+  }
+
+  pub fn jxl_options(&self) -> JxlOptions {
+    self.jxl.threads(self.threads_per_image())
   }
 
-  pub async fn convert_to_avif(input_path: &str, output_path: &str) -> Result<bool, Error> {
-    avif::AvifConverter::convert(input_path, output_path).await
+  pub fn png_options(&self) -> PngOptions {
+    self.png
   }
 }
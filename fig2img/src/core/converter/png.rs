@@ -0,0 +1,369 @@
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use color_quant::NeuQuant;
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use oxipng::{BitDepth, ColorType, RawImage, StripChunks};
+use rgb::RGBA8;
+use serde::{Deserialize, Serialize};
+
+use super::{Converter, SourceImage};
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PngOptions {
+  /// Effort spent searching filters and deflate settings, 0 (fastest) to 6 (smallest files).
+  level: u8,
+  /// Removes metadata that doesn't change how the image looks, such as text and timestamps.
+  strip: bool,
+  /// Reduces the image to a palette of at most this many colors (2 to 256). Lossy.
+  palette: Option<u16>,
+  /// Strength of the dithering used with `palette`, from 0 (none, flat areas stay clean) to 1.
+  dithering: f32,
+  /// Lowest acceptable quality of the palette image, 0 to 100. Images that would look worse are
+  /// recompressed losslessly instead.
+  min_quality: u8,
+}
+
+impl Default for PngOptions {
+  fn default() -> Self {
+    Self {
+      level: 2,
+      strip: true,
+      palette: None,
+      dithering: 1.0,
+      min_quality: 0,
+    }
+  }
+}
+
+impl PngOptions {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn level(mut self, level: u8) -> Self {
+    self.level = level;
+    self
+  }
+
+  pub fn strip(mut self, strip: bool) -> Self {
+    self.strip = strip;
+    self
+  }
+
+  pub fn palette(mut self, palette: Option<u16>) -> Self {
+    self.palette = palette;
+    self
+  }
+
+  pub fn dithering(mut self, dithering: f32) -> Self {
+    self.dithering = dithering;
+    self
+  }
+
+  pub fn min_quality(mut self, min_quality: u8) -> Self {
+    self.min_quality = min_quality;
+    self
+  }
+
+  pub fn validate(&self) -> Result<(), Error> {
+    let invalid = |message: String| Err(Error::new(ErrorKind::InvalidInput, message));
+
+    if self.level > 6 {
+      return invalid(format!("PNG level must be 0-6, got {}", self.level));
+    }
+    if let Some(palette) = self.palette.filter(|colors| !(2..=256).contains(colors)) {
+      return invalid(format!(
+        "PNG palette must have 2-256 colors, got {}",
+        palette
+      ));
+    }
+    if !(0.0..=1.0).contains(&self.dithering) {
+      return invalid(format!("PNG dithering must be 0-1, got {}", self.dithering));
+    }
+    if self.min_quality > 100 {
+      return invalid(format!(
+        "PNG minimum quality must be 0-100, got {}",
+        self.min_quality
+      ));
+    }
+    Ok(())
+  }
+
+  fn oxipng(&self) -> oxipng::Options {
+    let mut options = oxipng::Options::from_preset(self.level);
+    if self.strip {
+      options.strip = StripChunks::Safe;
+    }
+    options
+  }
+}
+
+/// Writes smaller PNGs: losslessly by trying other filters, deflate settings and color types, or
+/// lossily by reducing the image to a palette.
+pub struct PngConverter {
+  options: PngOptions,
+}
+
+impl PngConverter {
+  pub fn new(options: PngOptions) -> Self {
+    Self { options }
+  }
+
+  fn encode_lossless(&self, image: &DynamicImage) -> Result<Vec<u8>, Error> {
+    let (width, height) = (image.width(), image.height());
+    // oxipng reduces the bit depth and color type again when that is lossless.
+    let raw = match image.color().bytes_per_pixel() / image.color().channel_count() {
+      1 => RawImage::new(
+        width,
+        height,
+        ColorType::RGBA,
+        BitDepth::Eight,
+        image.to_rgba8().into_raw(),
+      ),
+      _ => RawImage::new(
+        width,
+        height,
+        ColorType::RGBA,
+        BitDepth::Sixteen,
+        image
+          .to_rgba16()
+          .into_raw()
+          .into_iter()
+          .flat_map(u16::to_be_bytes)
+          .collect(),
+      ),
+    };
+
+    raw
+      .and_then(|raw| raw.create_optimized_png(&self.options.oxipng()))
+      .map_err(|e| Error::other(format!("PNG optimization failed: {}", e)))
+  }
+
+  /// The palette image, or `None` when it falls below the minimum quality.
+  fn encode_palette(&self, image: &DynamicImage, colors: u16) -> Result<Option<Vec<u8>>, Error> {
+    let rgba = image.to_rgba8();
+    let (palette, indices) = quantize(&rgba, colors.into(), self.options.dithering);
+
+    let quality = quality(&rgba, &palette, &indices);
+    if quality < f64::from(self.options.min_quality) {
+      tracing::warn!(
+        quality = format!("{:.0}", quality),
+        min_quality = self.options.min_quality,
+        "palette image is below the minimum quality, keeping all colors"
+      );
+      return Ok(None);
+    }
+
+    let palette = palette
+      .chunks_exact(4)
+      .map(|c| RGBA8::new(c[0], c[1], c[2], c[3]))
+      .collect();
+    RawImage::new(
+      rgba.width(),
+      rgba.height(),
+      ColorType::Indexed { palette },
+      BitDepth::Eight,
+      indices,
+    )
+    .and_then(|raw| raw.create_optimized_png(&self.options.oxipng()))
+    .map(Some)
+    .map_err(|e| Error::other(format!("PNG optimization failed: {}", e)))
+  }
+}
+
+impl Converter for PngConverter {
+  fn name(&self) -> &str {
+    "png"
+  }
+
+  fn extension(&self) -> &str {
+    "png"
+  }
+
+  fn mime_type(&self) -> &str {
+    "image/png"
+  }
+
+  fn validate(&self) -> Result<(), Error> {
+    self.options.validate()
+  }
+
+  fn encode(&self, image: &DynamicImage) -> Result<Vec<u8>, Error> {
+    if let Some(colors) = self.options.palette {
+      if let Some(encoded) = self.encode_palette(image, colors)? {
+        return Ok(encoded);
+      }
+    }
+    self.encode_lossless(image)
+  }
+
+  // Lossless recompression works on the PNG itself, which keeps chunks such as the color profile.
+  fn convert_source(&self, source: &SourceImage, output_path: &Path) -> Result<(), Error> {
+    let data = match source.file() {
+      Some(file) if self.options.palette.is_none() => Some(std::fs::read(file)?),
+      _ => None,
+    };
+
+    let encoded = match data {
+      Some(data) if image::guess_format(&data).is_ok_and(|format| format == ImageFormat::Png) => {
+        oxipng::optimize_from_memory(&data, &self.options.oxipng())
+          .map_err(|e| Error::other(format!("PNG optimization failed: {}", e)))?
+      }
+      _ => self.encode(source.image()?)?,
+    };
+
+    std::fs::write(output_path, encoded)
+  }
+}
+
+/// Picks a palette of up to `colors` RGBA colors and maps every pixel to it, diffusing the error
+/// to neighbouring pixels (Floyd-Steinberg) scaled by `dithering`.
+fn quantize(image: &RgbaImage, colors: usize, dithering: f32) -> (Vec<u8>, Vec<u8>) {
+  let quantizer = NeuQuant::new(10, colors, image.as_raw());
+  let palette = quantizer.color_map_rgba();
+
+  let (width, height) = (image.width() as usize, image.height() as usize);
+  let mut errors = vec![[0f32; 4]; width * height];
+  let mut indices = Vec::with_capacity(width * height);
+
+  for (i, pixel) in image.pixels().enumerate() {
+    let wanted: [f32; 4] = std::array::from_fn(|c| f32::from(pixel.0[c]) + errors[i][c]);
+    let index = quantizer.index_of(&wanted.map(|v| v.round().clamp(0.0, 255.0) as u8));
+    indices.push(index as u8);
+
+    if dithering == 0.0 {
+      continue;
+    }
+    let chosen = &palette[index * 4..index * 4 + 4];
+    let error: [f32; 4] = std::array::from_fn(|c| (wanted[c] - f32::from(chosen[c])) * dithering);
+    let (x, y) = (i % width, i / width);
+    for (dx, dy, weight) in [(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)] {
+      let (nx, ny) = (x as isize + dx, y + dy);
+      if nx < 0 || nx as usize >= width || ny >= height {
+        continue;
+      }
+      let neighbour = &mut errors[ny * width + nx as usize];
+      for c in 0..4 {
+        neighbour[c] += error[c] * weight / 16.0;
+      }
+    }
+  }
+
+  (palette, indices)
+}
+
+/// How close the palette image is to the original, from 0 (20 dB PSNR or worse) to 100 (45 dB or
+/// better).
+fn quality(image: &RgbaImage, palette: &[u8], indices: &[u8]) -> f64 {
+  let squared_error: f64 = image
+    .pixels()
+    .zip(indices)
+    .flat_map(|(pixel, &index)| {
+      let chosen = &palette[usize::from(index) * 4..usize::from(index) * 4 + 4];
+      pixel.0.into_iter().zip(chosen).map(|(a, &b)| {
+        let diff = f64::from(a) - f64::from(b);
+        diff * diff
+      })
+    })
+    .sum();
+
+  let mse = squared_error / (indices.len().max(1) * 4) as f64;
+  if mse == 0.0 {
+    return 100.0;
+  }
+  let psnr = 10.0 * (255.0 * 255.0 / mse).log10();
+  ((psnr - 20.0) / 25.0 * 100.0).clamp(0.0, 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+  use image::{ImageBuffer, Rgba};
+  use tempfile::tempdir;
+
+  use super::*;
+
+  fn gradient() -> RgbaImage {
+    RgbaImage::from_fn(64, 64, |x, y| {
+      Rgba([x as u8 * 4, y as u8 * 4, 255 - x as u8 * 2, 255 - y as u8])
+    })
+  }
+
+  #[test]
+  fn test_lossless_recompression_keeps_pixels() {
+    let dir = tempdir().unwrap();
+    let input_path = dir.path().join("in.png");
+    let output_path = dir.path().join("out.png");
+    let image = gradient();
+    image.save(&input_path).unwrap();
+
+    PngConverter::new(PngOptions::new().level(3))
+      .convert(&input_path, &output_path)
+      .unwrap();
+
+    let output = std::fs::read(&output_path).unwrap();
+    assert!(output.len() as u64 <= std::fs::metadata(&input_path).unwrap().len());
+    assert_eq!(image::load_from_memory(&output).unwrap().to_rgba8(), image);
+  }
+
+  #[test]
+  fn test_lossless_encoding_keeps_16_bit_samples() {
+    let image: ImageBuffer<Rgba<u16>, _> = ImageBuffer::from_fn(8, 8, |x, y| {
+      Rgba([x as u16 * 4097, y as u16 * 257, 1, 65535])
+    });
+    let encoded = PngConverter::new(PngOptions::new())
+      .encode(&DynamicImage::ImageRgba16(image.clone()))
+      .unwrap();
+
+    assert_eq!(
+      image::load_from_memory(&encoded).unwrap().to_rgba16(),
+      image
+    );
+  }
+
+  #[test]
+  fn test_palette_quantization() {
+    let image = DynamicImage::ImageRgba8(gradient());
+
+    for dithering in [0.0, 1.0] {
+      let options = PngOptions::new().palette(Some(16)).dithering(dithering);
+      let encoded = PngConverter::new(options).encode(&image).unwrap();
+
+      let decoded = image::load_from_memory(&encoded).unwrap().to_rgba8();
+      let mut colors = decoded.pixels().map(|p| p.0).collect::<Vec<_>>();
+      colors.sort_unstable();
+      colors.dedup();
+      assert!(colors.len() <= 16, "{} colors", colors.len());
+      assert_eq!(decoded.dimensions(), (64, 64));
+    }
+  }
+
+  #[test]
+  fn test_quality_floor_falls_back_to_lossless() {
+    let image = DynamicImage::ImageRgba8(gradient());
+    let options = PngOptions::new().palette(Some(2)).min_quality(90);
+
+    let encoded = PngConverter::new(options).encode(&image).unwrap();
+
+    assert_eq!(
+      image::load_from_memory(&encoded).unwrap().to_rgba8(),
+      image.to_rgba8()
+    );
+  }
+
+  #[test]
+  fn test_quality() {
+    let image = RgbaImage::from_pixel(2, 1, Rgba([10, 20, 30, 255]));
+    assert_eq!(quality(&image, &[10, 20, 30, 255], &[0, 0]), 100.0);
+    assert_eq!(quality(&image, &[255, 255, 255, 0], &[0, 0]), 0.0);
+  }
+
+  #[test]
+  fn test_validate_rejects_out_of_range_options() {
+    assert!(PngOptions::new().validate().is_ok());
+    assert!(PngOptions::new().level(7).validate().is_err());
+    assert!(PngOptions::new().palette(Some(257)).validate().is_err());
+    assert!(PngOptions::new().dithering(1.5).validate().is_err());
+  }
+}
@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use super::{
+  AvifConverter, Converter, EncoderOptions, JpegConverter, JxlConverter, PngConverter,
+  WebPConverter,
+};
+
+/// Converters by name. Names are matched case-insensitively, and registering a name again
+/// replaces the previous converter, so built-in formats can be swapped out.
+#[derive(Clone, Default)]
+pub struct ConverterRegistry {
+  converters: BTreeMap<String, Arc<dyn Converter>>,
+}
+
+impl ConverterRegistry {
+  /// An empty registry.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// A registry with the built-in formats, configured with `options`.
+  pub fn with_defaults(options: &EncoderOptions) -> Self {
+    let mut registry = Self::new();
+    registry.register(WebPConverter::new(options.webp_options()));
+    registry.register(AvifConverter::new(options.avif_options()));
+    registry.register(JpegConverter::new(options.jpeg_options()));
+    registry.register(JxlConverter::new(options.jxl_options()));
+    registry.register(PngConverter::new(options.png_options()));
+    registry
+  }
+
+  pub fn register(&mut self, converter: impl Converter + 'static) {
+    self.register_arc(Arc::new(converter));
+  }
+
+  pub fn register_arc(&mut self, converter: Arc<dyn Converter>) {
+    self
+      .converters
+      .insert(converter.name().to_ascii_lowercase(), converter);
+  }
+
+  pub fn get(&self, name: &str) -> Option<Arc<dyn Converter>> {
+    self.converters.get(&name.to_ascii_lowercase()).cloned()
+  }
+
+  /// Registered names, in alphabetical order.
+  pub fn names(&self) -> impl Iterator<Item = &str> {
+    self.converters.keys().map(String::as_str)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Error;
+
+  use image::DynamicImage;
+
+  use super::*;
+
+  struct RawConverter;
+
+  impl Converter for RawConverter {
+    fn name(&self) -> &str {
+      "raw"
+    }
+
+    fn extension(&self) -> &str {
+      "rgba"
+    }
+
+    fn mime_type(&self) -> &str {
+      "application/octet-stream"
+    }
+
+    fn encode(&self, image: &DynamicImage) -> Result<Vec<u8>, Error> {
+      Ok(image.to_rgba8().into_raw())
+    }
+  }
+
+  #[test]
+  fn test_with_defaults() {
+    let registry = ConverterRegistry::with_defaults(&EncoderOptions::new());
+
+    assert_eq!(
+      registry.names().collect::<Vec<_>>(),
+      ["avif", "jpeg", "jxl", "png", "webp"]
+    );
+    assert_eq!(registry.get("WebP").unwrap().mime_type(), "image/webp");
+    assert!(registry.get("gif").is_none());
+  }
+
+  #[test]
+  fn test_register_custom_converter() {
+    let mut registry = ConverterRegistry::with_defaults(&EncoderOptions::new());
+    registry.register(RawConverter);
+
+    let converter = registry.get("raw").unwrap();
+    let image = DynamicImage::ImageRgba8(image::RgbaImage::new(2, 2));
+    assert_eq!(converter.extension(), "rgba");
+    assert_eq!(converter.encode(&image).unwrap().len(), 16);
+  }
+}
@@ -1,49 +1,311 @@
-use std::io::Error;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
 use std::process::Command;
+use std::str::FromStr;
 
-pub(super) struct WebPConverter;
+use image::{DynamicImage, ImageFormat};
+use serde::{Deserialize, Serialize};
+
+use super::{Converter, SourceImage};
+
+const CWEBP: &str = "cwebp";
+
+/// How WebP files are encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebPBackend {
+  /// In-process encoding with the bundled libwebp. Needs nothing installed.
+  #[default]
+  Native,
+  /// Runs the `cwebp` command line tool, which must be on the `PATH`.
+  Cwebp,
+}
+
+impl FromStr for WebPBackend {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_ascii_lowercase().as_str() {
+      "native" => Ok(Self::Native),
+      "cwebp" => Ok(Self::Cwebp),
+      _ => Err(format!("Unsupported WebP backend: {}", s)),
+    }
+  }
+}
+
+impl fmt::Display for WebPBackend {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Self::Native => "native",
+      Self::Cwebp => "cwebp",
+    })
+  }
+}
+
+/// Defaults match `cwebp`'s, so both backends produce comparable files.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WebPOptions {
+  /// 0 (smallest) to 100 (best). For lossless output, how hard to try to compress.
+  quality: f32,
+  /// Quality of the alpha channel, 0 to 100.
+  alpha_quality: u8,
+  /// Compression effort, 0 (fastest) to 6 (smallest files).
+  method: u8,
+  lossless: bool,
+  /// Lossless encoding with pixel values adjusted for better compression; 100 leaves them
+  /// untouched, 0 adjusts the most. Implies `lossless`.
+  near_lossless: Option<u8>,
+  /// Slower but more accurate RGB to YUV conversion, for sharper edges on lossy output.
+  sharp_yuv: bool,
+  backend: WebPBackend,
+  /// Threads per image, filled in from `EncoderOptions`. `None` lets the encoder decide.
+  #[serde(skip)]
+  threads: Option<usize>,
+}
+
+impl Default for WebPOptions {
+  fn default() -> Self {
+    Self {
+      quality: 75.0,
+      alpha_quality: 100,
+      method: 4,
+      lossless: false,
+      near_lossless: None,
+      sharp_yuv: false,
+      backend: WebPBackend::default(),
+      threads: None,
+    }
+  }
+}
+
+impl WebPOptions {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn quality(mut self, quality: f32) -> Self {
+    self.quality = quality;
+    self
+  }
+
+  pub fn alpha_quality(mut self, alpha_quality: u8) -> Self {
+    self.alpha_quality = alpha_quality;
+    self
+  }
+
+  pub fn method(mut self, method: u8) -> Self {
+    self.method = method;
+    self
+  }
+
+  pub fn lossless(mut self, lossless: bool) -> Self {
+    self.lossless = lossless;
+    self
+  }
+
+  pub fn near_lossless(mut self, near_lossless: Option<u8>) -> Self {
+    self.near_lossless = near_lossless;
+    self
+  }
+
+  pub fn sharp_yuv(mut self, sharp_yuv: bool) -> Self {
+    self.sharp_yuv = sharp_yuv;
+    self
+  }
+
+  pub fn backend(mut self, backend: WebPBackend) -> Self {
+    self.backend = backend;
+    self
+  }
+
+  pub fn threads(mut self, threads: Option<usize>) -> Self {
+    self.threads = threads;
+    self
+  }
+
+  pub fn validate(&self) -> Result<(), Error> {
+    let invalid = |message: String| Err(Error::new(ErrorKind::InvalidInput, message));
+
+    if !(0.0..=100.0).contains(&self.quality) {
+      return invalid(format!("WebP quality must be 0-100, got {}", self.quality));
+    }
+    if self.alpha_quality > 100 {
+      return invalid(format!(
+        "WebP alpha quality must be 0-100, got {}",
+        self.alpha_quality
+      ));
+    }
+    if self.method > 6 {
+      return invalid(format!("WebP method must be 0-6, got {}", self.method));
+    }
+    if let Some(near_lossless) = self.near_lossless.filter(|&n| n > 100) {
+      return invalid(format!(
+        "WebP near-lossless must be 0-100, got {}",
+        near_lossless
+      ));
+    }
+    Ok(())
+  }
+
+  fn is_lossless(&self) -> bool {
+    self.lossless || self.near_lossless.is_some()
+  }
+
+  fn config(&self) -> Result<webp::WebPConfig, Error> {
+    let mut config =
+      webp::WebPConfig::new().map_err(|_| Error::other("Failed to initialize WebP config"))?;
+    config.quality = self.quality;
+    config.alpha_quality = self.alpha_quality.into();
+    config.method = self.method.into();
+    config.lossless = self.is_lossless().into();
+    config.near_lossless = self.near_lossless.unwrap_or(100).into();
+    config.use_sharp_yuv = self.sharp_yuv.into();
+    config.thread_level = self.is_multithreaded().into();
+    Ok(config)
+  }
+
+  /// libwebp can only use one extra thread, for lossy encoding.
+  fn is_multithreaded(&self) -> bool {
+    self.threads.is_some_and(|threads| threads > 1)
+  }
+
+  fn cwebp_args(&self) -> Vec<String> {
+    let mut args = vec![
+      "-q".to_string(),
+      self.quality.to_string(),
+      "-alpha_q".to_string(),
+      self.alpha_quality.to_string(),
+      "-m".to_string(),
+      self.method.to_string(),
+    ];
+    if self.is_lossless() {
+      args.push("-lossless".to_string());
+    }
+    if let Some(near_lossless) = self.near_lossless {
+      args.extend(["-near_lossless".to_string(), near_lossless.to_string()]);
+    }
+    if self.sharp_yuv {
+      args.push("-sharp_yuv".to_string());
+    }
+    if self.is_multithreaded() {
+      args.push("-mt".to_string());
+    }
+    args
+  }
+}
+
+pub struct WebPConverter {
+  options: WebPOptions,
+  /// The `cwebp` program run by the `Cwebp` backend.
+  program: String,
+}
 
 impl WebPConverter {
-  pub async fn convert(input_path: &str, output_path: &str) -> Result<bool, Error> {
-    Self::convert_inner(input_path, output_path)
+  pub fn new(options: WebPOptions) -> Self {
+    Self::with_program(options, CWEBP)
   }
 
-  fn convert_inner(input_path: &str, output_path: &str) -> Result<bool, Error> {
-    if !Self::check_cwebp_installed() {
-      Self::print_installation_guide();
-      return Ok(false);
+  fn with_program(options: WebPOptions, program: &str) -> Self {
+    Self {
+      options,
+      program: program.to_string(),
     }
+  }
+
+  fn encode_native(&self, image: &DynamicImage) -> Result<Vec<u8>, Error> {
+    let rgba = image.to_rgba8();
+    let encoded = webp::Encoder::from_rgba(rgba.as_raw(), rgba.width(), rgba.height())
+      .encode_advanced(&self.options.config()?)
+      .map_err(|e| Error::other(format!("WebP encoding failed: {:?}", e)))?;
+
+    Ok(encoded.to_vec())
+  }
 
-    let status = Command::new("cwebp")
+  fn run_cwebp(&self, input_path: &Path, output_path: &Path) -> Result<(), Error> {
+    if !self.check_cwebp_installed() {
+      return Err(Error::new(
+        ErrorKind::NotFound,
+        "cwebp is not installed. Install it (`brew install webp`, `apt install webp`) or use the \
+         native WebP backend",
+      ));
+    }
+
+    let status = Command::new(&self.program)
       .arg("-quiet")
+      .args(self.options.cwebp_args())
       .arg(input_path)
       .arg("-o")
       .arg(output_path)
       .status()?;
 
-    Ok(status.success())
+    match status.success() {
+      true => Ok(()),
+      false => Err(Error::other(format!("cwebp exited with {}", status))),
+    }
   }
 
-  fn check_cwebp_installed() -> bool {
-    Command::new("cwebp")
+  fn check_cwebp_installed(&self) -> bool {
+    Command::new(&self.program)
       .arg("-version")
       .output()
-      .map_or(false, |output| output.status.success())
+      .is_ok_and(|output| output.status.success())
+  }
+}
+
+impl Converter for WebPConverter {
+  fn name(&self) -> &str {
+    "webp"
   }
 
-  fn print_installation_guide() {
-    println!("cwebp is not installed. Please install it:");
-    println!("macOS: brew install webp");
-    // println!("Ubuntu: sudo apt-get install webp");
-    // println!("Windows: choco install webp");
-    println!("Or download from: https://developers.google.com/speed/webp/download");
+  fn extension(&self) -> &str {
+    "webp"
+  }
+
+  fn mime_type(&self) -> &str {
+    "image/webp"
+  }
+
+  fn validate(&self) -> Result<(), Error> {
+    self.options.validate()
+  }
+
+  fn encode(&self, image: &DynamicImage) -> Result<Vec<u8>, Error> {
+    match self.options.backend {
+      WebPBackend::Native => self.encode_native(image),
+      // cwebp only reads files, so hand it a lossless PNG.
+      WebPBackend::Cwebp => {
+        let temp_dir = super::temp_dir()?;
+        let input_path = temp_dir.path().join("input.png");
+        let output_path = temp_dir.path().join("output.webp");
+
+        image
+          .save_with_format(&input_path, ImageFormat::Png)
+          .map_err(Error::other)?;
+        self.run_cwebp(&input_path, &output_path)?;
+        std::fs::read(&output_path)
+      }
+    }
+  }
+
+  fn convert_source(&self, source: &SourceImage, output_path: &Path) -> Result<(), Error> {
+    match self.options.backend {
+      WebPBackend::Native => self
+        .encode_native(source.image()?)
+        .and_then(|encoded| std::fs::write(output_path, encoded)),
+      WebPBackend::Cwebp => match source.file() {
+        Some(file) => self.run_cwebp(file, output_path),
+        None => self
+          .encode(source.image()?)
+          .and_then(|encoded| std::fs::write(output_path, encoded)),
+      },
+    }
   }
 }
 
 #[cfg(test)]
 mod tests {
-  use std::path::Path;
-
   use tempfile::NamedTempFile;
 
   use super::*;
@@ -63,25 +325,72 @@ mod tests {
     temp_file
   }
 
-  #[tokio::test]
-  async fn test_webp_conversion() {
+  #[test]
+  fn test_webp_conversion() {
     let input_file = create_test_image();
     let output_file = NamedTempFile::new().unwrap();
 
-    let checked_cwebp = WebPConverter::check_cwebp_installed();
-    assert!(checked_cwebp, "❌ cwebp is not installed");
-
-    let result = WebPConverter::convert(
-      input_file.path().to_str().unwrap(),
-      output_file.path().to_str().unwrap(),
-    )
-    .await;
+    let result =
+      WebPConverter::new(WebPOptions::new()).convert(input_file.path(), output_file.path());
 
     assert!(result.is_ok(), "❌ Conversion failed: {:?}", result.err());
-    assert!(result.unwrap(), "❌ Conversion returned false");
+
+    let output = std::fs::read(output_file.path()).unwrap();
+    assert_eq!(
+      image::guess_format(&output).unwrap(),
+      image::ImageFormat::WebP,
+      "❌ Output is not a WebP file"
+    );
+    assert_eq!(
+      image::load_from_memory(&output)
+        .unwrap()
+        .into_rgba8()
+        .dimensions(),
+      (100, 100)
+    );
+  }
+
+  #[test]
+  fn test_lossless_encoding_preserves_pixels() {
+    let mut img = image::RgbaImage::new(16, 16);
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+      *pixel = image::Rgba([x as u8 * 16, y as u8 * 16, 128, 200]);
+    }
+
+    let options = WebPOptions::new().lossless(true).method(6);
+    let encoded = WebPConverter::new(options)
+      .encode(&DynamicImage::ImageRgba8(img.clone()))
+      .unwrap();
+
+    assert_eq!(image::load_from_memory(&encoded).unwrap().to_rgba8(), img);
+  }
+
+  #[test]
+  fn test_validate_rejects_out_of_range_options() {
+    assert!(WebPOptions::new().validate().is_ok());
+    assert!(WebPOptions::new().method(7).validate().is_err());
+    assert!(WebPOptions::new().quality(101.0).validate().is_err());
+    assert!(WebPOptions::new()
+      .near_lossless(Some(150))
+      .validate()
+      .is_err());
+  }
+
+  #[test]
+  fn test_cwebp_backend_fails_when_not_installed() {
+    let input_file = create_test_image();
+    let output_file = NamedTempFile::new().unwrap();
+
+    let options = WebPOptions::new().backend(WebPBackend::Cwebp);
+    let error = WebPConverter::with_program(options, "fig2img-test-missing-cwebp")
+      .convert(input_file.path(), output_file.path())
+      .unwrap_err();
+
+    assert_eq!(error.kind(), ErrorKind::NotFound);
     assert!(
-      Path::new(output_file.path()).exists(),
-      "❌ Output file does not exist"
+      error.to_string().contains("cwebp is not installed"),
+      "{}",
+      error
     );
   }
 }
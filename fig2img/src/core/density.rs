@@ -0,0 +1,130 @@
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageBuffer, Rgba, RgbaImage};
+
+type LinearImage = ImageBuffer<Rgba<f32>, Vec<f32>>;
+
+/// File name suffix for a pixel density: none at 1x, `@2x` at 2x, `@1.5x` at 1.5x.
+pub fn scale_suffix(scale: f32) -> String {
+  if scale == 1.0 {
+    return String::new();
+  }
+  format!("@{}x", scale)
+}
+
+/// Downsamples `image` to `width` x `height` in linear light, so that fine detail such as thin
+/// text and 1px strokes keeps its brightness instead of darkening as it would when averaging sRGB
+/// values. `sharpen` (0 for none, around 0.5 for a subtle effect) restores some of the crispness
+/// lost by resampling.
+pub fn downsample(image: &DynamicImage, width: u32, height: u32, sharpen: f32) -> DynamicImage {
+  let linear = to_linear_premultiplied(&image.to_rgba8());
+  let mut resized = imageops::resize(&linear, width, height, FilterType::Lanczos3);
+  if sharpen > 0.0 {
+    resized = unsharp_mask(&resized, sharpen);
+  }
+  DynamicImage::ImageRgba8(to_srgb(&resized))
+}
+
+/// Converts to linear light with color premultiplied by alpha, so transparent pixels don't bleed
+/// their hidden color into the edges.
+fn to_linear_premultiplied(image: &RgbaImage) -> LinearImage {
+  ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+    let [r, g, b, a] = image.get_pixel(x, y).0;
+    let alpha = f32::from(a) / 255.0;
+    Rgba([
+      srgb_to_linear(r) * alpha,
+      srgb_to_linear(g) * alpha,
+      srgb_to_linear(b) * alpha,
+      alpha,
+    ])
+  })
+}
+
+fn to_srgb(image: &LinearImage) -> RgbaImage {
+  ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+    let [r, g, b, a] = image.get_pixel(x, y).0;
+    let alpha = a.clamp(0.0, 1.0);
+    if alpha == 0.0 {
+      return Rgba([0, 0, 0, 0]);
+    }
+    Rgba([
+      linear_to_srgb(r / alpha),
+      linear_to_srgb(g / alpha),
+      linear_to_srgb(b / alpha),
+      (alpha * 255.0).round() as u8,
+    ])
+  })
+}
+
+/// Adds `amount` times the difference between the image and a slightly blurred copy of it.
+fn unsharp_mask(image: &LinearImage, amount: f32) -> LinearImage {
+  let blurred = imageops::blur(image, 0.8);
+  ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+    let (pixel, blur) = (image.get_pixel(x, y).0, blurred.get_pixel(x, y).0);
+    let alpha = (pixel[3] + (pixel[3] - blur[3]) * amount).clamp(0.0, 1.0);
+    // Premultiplied color can't exceed its alpha.
+    let channel = |c: usize| (pixel[c] + (pixel[c] - blur[c]) * amount).clamp(0.0, alpha);
+    Rgba([channel(0), channel(1), channel(2), alpha])
+  })
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+  let value = f32::from(value) / 255.0;
+  match value <= 0.04045 {
+    true => value / 12.92,
+    false => ((value + 0.055) / 1.055).powf(2.4),
+  }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+  let value = value.clamp(0.0, 1.0);
+  let srgb = match value <= 0.003_130_8 {
+    true => value * 12.92,
+    false => 1.055 * value.powf(1.0 / 2.4) - 0.055,
+  };
+  (srgb * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_scale_suffix() {
+    assert_eq!(scale_suffix(1.0), "");
+    assert_eq!(scale_suffix(2.0), "@2x");
+    assert_eq!(scale_suffix(1.5), "@1.5x");
+  }
+
+  #[test]
+  fn test_downsample_averages_in_linear_light() {
+    // Alternating black and white columns average to 50% linear light, which is sRGB 188, not
+    // the 128 a naive sRGB average gives.
+    let stripes = RgbaImage::from_fn(64, 64, |x, _| match x % 2 {
+      0 => Rgba([0, 0, 0, 255]),
+      _ => Rgba([255, 255, 255, 255]),
+    });
+
+    let resized = downsample(&DynamicImage::ImageRgba8(stripes), 32, 32, 0.0).to_rgba8();
+
+    let center = resized.get_pixel(16, 16).0;
+    assert!((185..=191).contains(&center[0]), "{:?}", center);
+    assert_eq!(center[3], 255);
+  }
+
+  #[test]
+  fn test_downsample_keeps_transparent_edges_clean() {
+    // Fully transparent pixels with a hidden red color must not tint the opaque blue half.
+    let image = RgbaImage::from_fn(32, 32, |x, _| match x < 16 {
+      true => Rgba([255, 0, 0, 0]),
+      false => Rgba([0, 0, 255, 255]),
+    });
+
+    let resized = downsample(&DynamicImage::ImageRgba8(image), 16, 16, 0.5).to_rgba8();
+
+    for pixel in resized.pixels().filter(|p| p.0[3] > 0) {
+      assert!(pixel.0[0] < 8, "{:?}", pixel);
+    }
+    assert_eq!(resized.get_pixel(0, 8).0[3], 0);
+    assert_eq!(resized.get_pixel(12, 8).0, [0, 0, 255, 255]);
+  }
+}
@@ -1,41 +1,407 @@
+use std::collections::HashMap;
 use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use reqwest::Client;
-use tokio::fs::File;
+use reqwest::header::{
+  ACCEPT_RANGES, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE,
+};
+use reqwest::{Client, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, File, OpenOptions};
 use tokio::io::AsyncWriteExt;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 
+use crate::core::validator::{self, ValidatedImage};
+use crate::utils::{hash, redact};
+
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+pub const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+/// Longest wait between two attempts, however many retries are allowed.
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+pub const DEFAULT_JOBS: usize = 8;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Outcome of a failed download attempt.
+#[derive(Debug)]
+enum AttemptError {
+  /// Worth retrying: timeouts, connection failures, 5xx and similar.
+  Transient(BoxError),
+  Fatal(BoxError),
+}
+
+impl From<std::io::Error> for AttemptError {
+  fn from(error: std::io::Error) -> Self {
+    Self::Fatal(error.into())
+  }
+}
+
+impl From<reqwest::Error> for AttemptError {
+  fn from(error: reqwest::Error) -> Self {
+    // The URL may be pre-signed; the file name already identifies the download in errors.
+    let error = error.without_url();
+    if error.is_timeout() || error.is_connect() || error.is_request() || error.is_body() {
+      Self::Transient(error.into())
+    } else {
+      Self::Fatal(error.into())
+    }
+  }
+}
+
+/// What a partial download was fetched from, kept next to it so that it is only resumed against
+/// the same response.
+#[derive(Deserialize, Serialize)]
+struct PartialInfo {
+  /// SHA-256 of the URL, which may be pre-signed.
+  url_hash: String,
+  /// Strong ETag or Last-Modified of the response, sent back as `If-Range`.
+  validator: String,
+}
+
+/// Downloads images through a single connection-pooled client. Cloning the client is cheap, so
+/// one downloader can be shared by every download of a run.
 pub struct ImageDownloader {
   client: Client,
+  max_retries: u32,
+  retry_backoff: Duration,
+  jobs: Arc<Semaphore>,
+  max_per_host: Option<usize>,
+  hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+pub fn build_client(
+  connect_timeout: Duration,
+  read_timeout: Duration,
+) -> Result<Client, reqwest::Error> {
+  Client::builder()
+    .connect_timeout(connect_timeout)
+    .read_timeout(read_timeout)
+    .build()
+}
+
+impl Default for ImageDownloader {
+  fn default() -> Self {
+    Self::new()
+  }
 }
 
 impl ImageDownloader {
+  /// Panics if the HTTP client can't be built, like [`Client::new`].
   pub fn new() -> Self {
+    Self::with_timeouts(DEFAULT_CONNECT_TIMEOUT, DEFAULT_READ_TIMEOUT)
+      .expect("Failed to build the HTTP client")
+  }
+
+  pub fn with_timeouts(
+    connect_timeout: Duration,
+    read_timeout: Duration,
+  ) -> Result<Self, reqwest::Error> {
+    build_client(connect_timeout, read_timeout).map(Self::with_client)
+  }
+
+  pub fn with_client(client: Client) -> Self {
     Self {
-      client: Client::new(),
+      client,
+      max_retries: DEFAULT_MAX_RETRIES,
+      retry_backoff: DEFAULT_RETRY_BACKOFF,
+      jobs: Arc::new(Semaphore::new(DEFAULT_JOBS)),
+      max_per_host: None,
+      hosts: Mutex::new(HashMap::new()),
     }
   }
 
-  #[cfg(test)]
-  pub fn with_client(client: Client) -> Self {
-    Self { client }
+  /// Maximum number of downloads in flight at once.
+  pub fn jobs(mut self, jobs: usize) -> Self {
+    self.jobs = Arc::new(Semaphore::new(jobs.max(1)));
+    self
   }
 
-  pub async fn download(
+  /// Maximum number of downloads in flight at once against a single host.
+  pub fn max_per_host(mut self, max_per_host: Option<usize>) -> Self {
+    self.max_per_host = max_per_host.map(|n| n.max(1));
+    self
+  }
+
+  pub fn max_retries(mut self, max_retries: u32) -> Self {
+    self.max_retries = max_retries;
+    self
+  }
+
+  pub fn retry_backoff(mut self, retry_backoff: Duration) -> Self {
+    self.retry_backoff = retry_backoff;
+    self
+  }
+
+  pub async fn download(&self, image_url: &str, filename: &str) -> Result<String, BoxError> {
+    let filename_path = Path::new(filename);
+    let temp_path = Self::temp_path(filename_path);
+
+    self.fetch(image_url, &temp_path).await?;
+
+    fs::rename(&temp_path, filename_path).await?;
+    let _ = fs::remove_file(Self::info_path(&temp_path)).await;
+    Ok(filename.to_string())
+  }
+
+  /// Like [`download`](Self::download), but only moves the file into place once its content has
+  /// been validated as a decodable PNG. `expected_size` is the node's size in pixels.
+  pub async fn download_validated(
     &self,
     image_url: &str,
     filename: &str,
-  ) -> Result<String, Box<dyn Error + Send + Sync>> {
-    let response = self.client.get(image_url).send().await?;
+    expected_size: Option<(u32, u32)>,
+  ) -> Result<ValidatedImage, BoxError> {
+    let filename_path = Path::new(filename);
+    let temp_path = Self::temp_path(filename_path);
+
+    let content_type = self.fetch(image_url, &temp_path).await?;
+    let bytes = fs::read(&temp_path).await?;
+    let _ = fs::remove_file(Self::info_path(&temp_path)).await;
 
-    if !response.status().is_success() {
-      return Err(format!("HTTP error: {}", response.status()).into());
+    // Decoding a large render takes a while; keep it off the async workers.
+    let validated = tokio::task::spawn_blocking(move || {
+      validator::validate_png(&bytes, content_type.as_deref(), expected_size)
+    })
+    .await
+    .map_err(std::io::Error::other)?;
+
+    match validated {
+      Ok(validated) => {
+        fs::rename(&temp_path, filename_path).await?;
+        Ok(validated)
+      }
+      Err(error) => {
+        let _ = fs::remove_file(&temp_path).await;
+        Err(error.into())
+      }
     }
+  }
 
-    let bytes = response.bytes().await?;
-    let mut file = File::create(filename).await?;
-    file.write_all(&bytes).await?;
+  /// Downloads `image_url` into `temp_path`, retrying transient failures. Returns the response's
+  /// content type.
+  #[tracing::instrument(
+    name = "download",
+    skip_all,
+    fields(url = %redact::url(image_url), file = %temp_path.display())
+  )]
+  async fn fetch(&self, image_url: &str, temp_path: &Path) -> Result<Option<String>, BoxError> {
+    let mut resumable = false;
+    let mut attempt = 0;
 
-    Ok(filename.to_string())
+    loop {
+      let permits = self.acquire(image_url).await;
+      let result = self
+        .try_download(image_url, temp_path, &mut resumable)
+        .await;
+      drop(permits);
+
+      match result {
+        Ok(content_type) => return Ok(content_type),
+        Err(AttemptError::Transient(error)) if attempt < self.max_retries => {
+          tracing::info!(attempt = attempt + 1, %error, "download failed, retrying");
+          tokio::time::sleep(self.retry_delay(attempt)).await;
+          attempt += 1;
+        }
+        Err(AttemptError::Transient(error)) | Err(AttemptError::Fatal(error)) => {
+          // Keep the partial file for the next run only if the server can resume it.
+          if !resumable {
+            let _ = fs::remove_file(temp_path).await;
+            let _ = fs::remove_file(Self::info_path(temp_path)).await;
+          }
+          return Err(error);
+        }
+      }
+    }
+  }
+
+  /// Exponential backoff before retry number `attempt + 1`, capped at [`MAX_RETRY_DELAY`].
+  fn retry_delay(&self, attempt: u32) -> Duration {
+    self
+      .retry_backoff
+      .saturating_mul(2u32.saturating_pow(attempt))
+      .min(MAX_RETRY_DELAY)
+  }
+
+  /// Waits for a free slot on the host of `image_url`, then for a global one. Slots are released
+  /// between retries so a backing-off download doesn't hold up the others.
+  async fn acquire(&self, image_url: &str) -> Vec<OwnedSemaphorePermit> {
+    let mut permits = Vec::with_capacity(2);
+
+    if let Some(host) = self.host_semaphore(image_url) {
+      permits.extend(host.acquire_owned().await.ok());
+    }
+    permits.extend(self.jobs.clone().acquire_owned().await.ok());
+
+    permits
+  }
+
+  fn host_semaphore(&self, image_url: &str) -> Option<Arc<Semaphore>> {
+    let max_per_host = self.max_per_host?;
+    let url = reqwest::Url::parse(image_url).ok()?;
+    let host = format!("{}:{}", url.host_str()?, url.port_or_known_default()?);
+
+    let mut hosts = self.hosts.lock().unwrap();
+    let semaphore = hosts
+      .entry(host)
+      .or_insert_with(|| Arc::new(Semaphore::new(max_per_host)));
+    Some(Arc::clone(semaphore))
+  }
+
+  /// Performs one GET, resuming from the bytes already in `temp_path` when they were fetched from
+  /// the same URL and the server confirms, through `If-Range`, that the content hasn't changed.
+  async fn try_download(
+    &self,
+    image_url: &str,
+    temp_path: &Path,
+    resumable: &mut bool,
+  ) -> Result<Option<String>, AttemptError> {
+    let mut offset = fs::metadata(temp_path).await.map_or(0, |m| m.len());
+    let validator = match offset {
+      0 => None,
+      _ => Self::resume_validator(image_url, temp_path).await,
+    };
+    if offset > 0 && validator.is_none() {
+      // Left over from another URL, or from a response that can't be checked: start over.
+      fs::remove_file(temp_path).await?;
+      offset = 0;
+    }
+
+    let mut request = self.client.get(image_url);
+    if let Some(validator) = &validator {
+      request = request
+        .header(RANGE, format!("bytes={}-", offset))
+        .header(IF_RANGE, validator);
+    }
+    let response = request.send().await?;
+    let status = response.status();
+    let response_validator = Self::response_validator(&response);
+    *resumable = response_validator.is_some()
+      && response
+        .headers()
+        .get(ACCEPT_RANGES)
+        .is_some_and(|v| v == "bytes");
+
+    if status == StatusCode::RANGE_NOT_SATISFIABLE {
+      // The partial file doesn't match what the server has; start over.
+      fs::remove_file(temp_path).await?;
+      return Err(AttemptError::Transient(
+        format!("HTTP error: {}", status).into(),
+      ));
+    }
+
+    if !status.is_success() {
+      let error = format!("HTTP error: {}", status).into();
+      return Err(match Self::is_transient_status(status) {
+        true => AttemptError::Transient(error),
+        false => AttemptError::Fatal(error),
+      });
+    }
+
+    let resumed =
+      status == StatusCode::PARTIAL_CONTENT && Self::content_range_start(&response) == Some(offset);
+    if status == StatusCode::PARTIAL_CONTENT && !resumed {
+      fs::remove_file(temp_path).await?;
+      return Err(AttemptError::Transient("Unexpected Content-Range".into()));
+    }
+
+    *resumable |= resumed;
+    if !resumed {
+      Self::write_partial_info(image_url, temp_path, response_validator).await?;
+    }
+
+    let content_type = response
+      .headers()
+      .get(CONTENT_TYPE)
+      .and_then(|v| v.to_str().ok())
+      .map(str::to_string);
+
+    Self::write_body(response, temp_path, resumed).await?;
+    Ok(content_type)
+  }
+
+  /// Streams the body into `temp_path`, appending when resuming. The file is only renamed to its
+  /// final name once fully written, so a crash never leaves a truncated image behind.
+  async fn write_body(
+    mut response: Response,
+    temp_path: &Path,
+    append: bool,
+  ) -> Result<(), AttemptError> {
+    let mut file = match append {
+      true => OpenOptions::new().append(true).open(temp_path).await?,
+      false => File::create(temp_path).await?,
+    };
+
+    while let Some(chunk) = response.chunk().await? {
+      file.write_all(&chunk).await?;
+    }
+
+    file.sync_all().await?;
+    Ok(())
+  }
+
+  fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error()
+      || status == StatusCode::TOO_MANY_REQUESTS
+      || status == StatusCode::REQUEST_TIMEOUT
+  }
+
+  fn content_range_start(response: &Response) -> Option<u64> {
+    let content_range = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let range = content_range.strip_prefix("bytes ")?;
+    range.split('-').next()?.trim().parse().ok()
+  }
+
+  /// The `If-Range` value to resume `temp_path` with, if it was fetched from `image_url`.
+  async fn resume_validator(image_url: &str, temp_path: &Path) -> Option<String> {
+    let bytes = fs::read(Self::info_path(temp_path)).await.ok()?;
+    let info: PartialInfo = serde_json::from_slice(&bytes).ok()?;
+    (info.url_hash == hash::content_hash(image_url.as_bytes())).then_some(info.validator)
+  }
+
+  /// A strong ETag, or failing that Last-Modified; weak ETags can't be used with `If-Range`.
+  fn response_validator(response: &Response) -> Option<String> {
+    let header = |name| response.headers().get(name)?.to_str().ok();
+    header(ETAG)
+      .filter(|etag| !etag.starts_with("W/"))
+      .or_else(|| header(LAST_MODIFIED))
+      .map(str::to_string)
+  }
+
+  async fn write_partial_info(
+    image_url: &str,
+    temp_path: &Path,
+    validator: Option<String>,
+  ) -> Result<(), AttemptError> {
+    let info_path = Self::info_path(temp_path);
+    let Some(validator) = validator else {
+      let _ = fs::remove_file(&info_path).await;
+      return Ok(());
+    };
+    let info = PartialInfo {
+      url_hash: hash::content_hash(image_url.as_bytes()),
+      validator,
+    };
+    let json = serde_json::to_vec(&info).map_err(std::io::Error::from)?;
+    fs::write(info_path, json).await?;
+    Ok(())
+  }
+
+  /// Records what the partial file at `temp_path` was fetched from.
+  fn info_path(temp_path: &Path) -> PathBuf {
+    let mut info_name = temp_path.file_name().unwrap_or_default().to_os_string();
+    info_name.push(".json");
+    temp_path.with_file_name(info_name)
+  }
+
+  /// Temporary file next to `filename`, so the final rename stays on the same filesystem.
+  fn temp_path(filename: &Path) -> PathBuf {
+    let mut temp_name = filename.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".part");
+    filename.with_file_name(temp_name)
   }
 }
 
@@ -84,6 +450,53 @@ mod tests {
     mock.assert();
   }
 
+  #[tokio::test]
+  async fn test_download_streams_to_temp_file() {
+    let server = MockServer::start();
+    let mock_image = vec![7; 64 * 1024];
+
+    server.mock(|when, then| {
+      when.method("GET").path("/large.png");
+      then.status(200).body(mock_image.clone());
+    });
+
+    let temp_dir = tempdir().unwrap();
+    let temp_file = temp_dir.path().join("large.png");
+    let temp_file_path = temp_file.to_str().unwrap();
+
+    let downloader = ImageDownloader::with_client(Client::new());
+    downloader
+      .download(&server.url("/large.png"), temp_file_path)
+      .await
+      .unwrap();
+
+    assert_eq!(fs::read(&temp_file).unwrap(), mock_image);
+    assert!(!ImageDownloader::temp_path(&temp_file).exists());
+  }
+
+  #[tokio::test]
+  async fn test_download_server_error_keeps_existing_file() {
+    let server = MockServer::start();
+
+    server.mock(|when, then| {
+      when.method("GET").path("/error.png");
+      then.status(500);
+    });
+
+    let temp_dir = tempdir().unwrap();
+    let temp_file = temp_dir.path().join("existing.png");
+    fs::write(&temp_file, [1, 2, 3]).unwrap();
+
+    let downloader = ImageDownloader::with_client(Client::new()).max_retries(0);
+    let result = downloader
+      .download(&server.url("/error.png"), temp_file.to_str().unwrap())
+      .await;
+
+    assert!(result.is_err());
+    assert_eq!(fs::read(&temp_file).unwrap(), vec![1, 2, 3]);
+    assert!(!ImageDownloader::temp_path(&temp_file).exists());
+  }
+
   #[tokio::test]
   async fn test_download_server_error() {
     let server = MockServer::start();
@@ -98,7 +511,7 @@ mod tests {
     let temp_file_path = temp_file.to_str().unwrap();
 
     let client = Client::new();
-    let downloader = ImageDownloader::with_client(client);
+    let downloader = ImageDownloader::with_client(client).retry_backoff(Duration::from_millis(1));
     let result = downloader
       .download(&server.url("/error.png"), temp_file_path)
       .await;
@@ -106,6 +519,340 @@ mod tests {
     assert!(result.is_err());
     assert!(!temp_file.exists());
 
+    mock.assert_hits(DEFAULT_MAX_RETRIES as usize + 1);
+  }
+
+  #[tokio::test]
+  async fn test_download_limits_concurrency_per_host() {
+    let server = MockServer::start_async().await;
+
+    server.mock(|when, then| {
+      when.method("GET");
+      then
+        .status(200)
+        .delay(Duration::from_millis(200))
+        .body([1, 2, 3]);
+    });
+
+    let temp_dir = tempdir().unwrap();
+    let downloader = ImageDownloader::with_client(Client::new())
+      .jobs(4)
+      .max_per_host(Some(1));
+
+    let start = std::time::Instant::now();
+    let downloads = (0..3).map(|i| {
+      let url = server.url(format!("/{}.png", i));
+      let path = temp_dir.path().join(format!("{}.png", i));
+      let downloader = &downloader;
+      async move { downloader.download(&url, path.to_str().unwrap()).await }
+    });
+    let results = futures::future::join_all(downloads).await;
+
+    assert!(results.iter().all(|r| r.is_ok()));
+    assert!(start.elapsed() >= Duration::from_millis(600));
+  }
+
+  #[tokio::test]
+  async fn test_host_semaphore_is_shared_per_host() {
+    let downloader = ImageDownloader::with_client(Client::new()).max_per_host(Some(2));
+
+    let a = downloader
+      .host_semaphore("https://s3.example.com/a.png")
+      .unwrap();
+    let b = downloader
+      .host_semaphore("https://s3.example.com/b.png")
+      .unwrap();
+    let c = downloader
+      .host_semaphore("https://other.example.com/c.png")
+      .unwrap();
+
+    assert!(Arc::ptr_eq(&a, &b));
+    assert!(!Arc::ptr_eq(&a, &c));
+    assert_eq!(a.available_permits(), 2);
+    assert!(ImageDownloader::with_client(Client::new())
+      .host_semaphore("https://s3.example.com/a.png")
+      .is_none());
+  }
+
+  #[tokio::test]
+  async fn test_download_validated() {
+    let server = MockServer::start();
+    let mut png = Vec::new();
+    image::RgbaImage::from_fn(8, 4, |x, _| image::Rgba([x as u8 * 30, 0, 0, 255]))
+      .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+      .unwrap();
+
+    server.mock(|when, then| {
+      when.method("GET").path("/valid.png");
+      then
+        .status(200)
+        .header("content-type", "image/png")
+        .body(png.clone());
+    });
+
+    let temp_dir = tempdir().unwrap();
+    let temp_file = temp_dir.path().join("valid.png");
+
+    let downloader = ImageDownloader::with_client(Client::new());
+    let validated = downloader
+      .download_validated(
+        &server.url("/valid.png"),
+        temp_file.to_str().unwrap(),
+        Some((8, 4)),
+      )
+      .await
+      .unwrap();
+
+    assert_eq!((validated.width, validated.height), (8, 4));
+    assert!(validated.warnings.is_empty());
+    assert_eq!(fs::read(&temp_file).unwrap(), png);
+  }
+
+  #[tokio::test]
+  async fn test_download_validated_rejects_html() {
+    let server = MockServer::start();
+
+    server.mock(|when, then| {
+      when.method("GET").path("/error-page.png");
+      then
+        .status(200)
+        .header("content-type", "text/html")
+        .body("<html>Access Denied</html>");
+    });
+
+    let temp_dir = tempdir().unwrap();
+    let temp_file = temp_dir.path().join("error-page.png");
+
+    let downloader = ImageDownloader::with_client(Client::new());
+    let result = downloader
+      .download_validated(
+        &server.url("/error-page.png"),
+        temp_file.to_str().unwrap(),
+        None,
+      )
+      .await;
+
+    assert!(result.is_err());
+    assert!(!temp_file.exists());
+    assert!(!ImageDownloader::temp_path(&temp_file).exists());
+  }
+
+  #[tokio::test]
+  async fn test_download_does_not_retry_client_error() {
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+      when.method("GET").path("/missing.png");
+      then.status(404);
+    });
+
+    let temp_dir = tempdir().unwrap();
+    let temp_file = temp_dir.path().join("missing.png");
+
+    let downloader = ImageDownloader::with_client(Client::new());
+    let result = downloader
+      .download(&server.url("/missing.png"), temp_file.to_str().unwrap())
+      .await;
+
+    assert!(result.is_err());
+    mock.assert_hits(1);
+  }
+
+  #[tokio::test]
+  async fn test_download_retries_until_success() {
+    let server = MockServer::start_async().await;
+    let mock_image = vec![1, 2, 3, 4];
+
+    let mut error_mock = server.mock(|when, then| {
+      when.method("GET").path("/flaky.png");
+      then.status(503);
+    });
+
+    let temp_dir = tempdir().unwrap();
+    let temp_file = temp_dir.path().join("flaky.png");
+    let temp_file_path = temp_file.to_str().unwrap().to_string();
+    let url = server.url("/flaky.png");
+
+    let download = tokio::spawn(async move {
+      ImageDownloader::with_client(Client::new())
+        .retry_backoff(Duration::from_millis(300))
+        .download(&url, &temp_file_path)
+        .await
+    });
+
+    while error_mock.hits() == 0 {
+      tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    error_mock.delete();
+    let success_mock = server.mock(|when, then| {
+      when.method("GET").path("/flaky.png");
+      then.status(200).body(mock_image.clone());
+    });
+
+    assert!(download.await.unwrap().is_ok());
+    assert_eq!(fs::read(&temp_file).unwrap(), mock_image);
+    success_mock.assert();
+  }
+
+  #[test]
+  fn test_retry_delay_is_capped() {
+    let downloader = ImageDownloader::with_client(Client::new());
+
+    assert_eq!(downloader.retry_delay(0), DEFAULT_RETRY_BACKOFF);
+    assert_eq!(downloader.retry_delay(2), DEFAULT_RETRY_BACKOFF * 4);
+    assert_eq!(downloader.retry_delay(20), MAX_RETRY_DELAY);
+    assert_eq!(downloader.retry_delay(u32::MAX), MAX_RETRY_DELAY);
+  }
+
+  #[tokio::test]
+  async fn test_download_read_timeout() {
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+      when.method("GET").path("/slow.png");
+      then
+        .status(200)
+        .delay(Duration::from_millis(500))
+        .body([1, 2, 3]);
+    });
+
+    let temp_dir = tempdir().unwrap();
+    let temp_file = temp_dir.path().join("slow.png");
+
+    let downloader =
+      ImageDownloader::with_timeouts(Duration::from_secs(1), Duration::from_millis(100))
+        .unwrap()
+        .max_retries(1)
+        .retry_backoff(Duration::from_millis(1));
+    let result = downloader
+      .download(&server.url("/slow.png"), temp_file.to_str().unwrap())
+      .await;
+
+    assert!(result.is_err());
+    assert!(!temp_file.exists());
+    mock.assert_hits(2);
+  }
+
+  #[tokio::test]
+  async fn test_download_resumes_partial_file() {
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+      when
+        .method("GET")
+        .path("/resume.png")
+        .header("range", "bytes=4-")
+        .header("if-range", "\"v1\"");
+      then
+        .status(206)
+        .header("content-range", "bytes 4-7/8")
+        .body([5, 6, 7, 8]);
+    });
+
+    let temp_dir = tempdir().unwrap();
+    let temp_file = temp_dir.path().join("resume.png");
+    let temp_path = ImageDownloader::temp_path(&temp_file);
+    let url = server.url("/resume.png");
+    fs::write(&temp_path, [1, 2, 3, 4]).unwrap();
+    ImageDownloader::write_partial_info(&url, &temp_path, Some("\"v1\"".to_string()))
+      .await
+      .unwrap();
+
+    let downloader = ImageDownloader::with_client(Client::new());
+    downloader
+      .download(&url, temp_file.to_str().unwrap())
+      .await
+      .unwrap();
+
+    assert_eq!(fs::read(&temp_file).unwrap(), vec![1, 2, 3, 4, 5, 6, 7, 8]);
+    assert!(!ImageDownloader::info_path(&temp_path).exists());
+    mock.assert();
+  }
+
+  #[tokio::test]
+  async fn test_download_discards_partial_file_from_another_url() {
+    let server = MockServer::start();
+
+    // A Range request would mean the stale partial file was resumed.
+    let range_mock = server.mock(|when, then| {
+      when.header_exists("range");
+      then.status(400);
+    });
+    let mock = server.mock(|when, then| {
+      when.method("GET").path("/new-render.png");
+      then
+        .status(200)
+        .header("etag", "\"v2\"")
+        .body([1, 2, 3, 4, 5, 6, 7, 8]);
+    });
+
+    let temp_dir = tempdir().unwrap();
+    let temp_file = temp_dir.path().join("render.png");
+    let temp_path = ImageDownloader::temp_path(&temp_file);
+    fs::write(&temp_path, [9, 9, 9, 9]).unwrap();
+    let old_url = server.url("/old-render.png");
+    ImageDownloader::write_partial_info(&old_url, &temp_path, Some("\"v1\"".to_string()))
+      .await
+      .unwrap();
+
+    let downloader = ImageDownloader::with_client(Client::new());
+    downloader
+      .download(&server.url("/new-render.png"), temp_file.to_str().unwrap())
+      .await
+      .unwrap();
+
+    assert_eq!(fs::read(&temp_file).unwrap(), vec![1, 2, 3, 4, 5, 6, 7, 8]);
+    range_mock.assert_hits(0);
     mock.assert();
   }
+
+  #[tokio::test]
+  async fn test_download_restarts_when_range_is_ignored() {
+    let server = MockServer::start();
+
+    server.mock(|when, then| {
+      when.method("GET").path("/full.png");
+      then.status(200).body([1, 2, 3, 4, 5, 6, 7, 8]);
+    });
+
+    let temp_dir = tempdir().unwrap();
+    let temp_file = temp_dir.path().join("full.png");
+    fs::write(ImageDownloader::temp_path(&temp_file), [9, 9]).unwrap();
+
+    let downloader = ImageDownloader::with_client(Client::new());
+    downloader
+      .download(&server.url("/full.png"), temp_file.to_str().unwrap())
+      .await
+      .unwrap();
+
+    assert_eq!(fs::read(&temp_file).unwrap(), vec![1, 2, 3, 4, 5, 6, 7, 8]);
+  }
+
+  #[tokio::test]
+  async fn test_download_keeps_partial_file_when_resumable() {
+    let server = MockServer::start();
+
+    server.mock(|when, then| {
+      when.method("GET").path("/resumable.png");
+      then
+        .status(500)
+        .header("accept-ranges", "bytes")
+        .header("etag", "\"v1\"");
+    });
+
+    let temp_dir = tempdir().unwrap();
+    let temp_file = temp_dir.path().join("resumable.png");
+    let temp_path = ImageDownloader::temp_path(&temp_file);
+    let url = server.url("/resumable.png");
+    fs::write(&temp_path, [1, 2]).unwrap();
+    ImageDownloader::write_partial_info(&url, &temp_path, Some("\"v1\"".to_string()))
+      .await
+      .unwrap();
+
+    let downloader = ImageDownloader::with_client(Client::new()).max_retries(0);
+    let result = downloader.download(&url, temp_file.to_str().unwrap()).await;
+
+    assert!(result.is_err());
+    assert!(ImageDownloader::temp_path(&temp_file).exists());
+  }
 }
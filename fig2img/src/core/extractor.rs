@@ -1,12 +1,44 @@
+use std::time::Duration;
+
 use reqwest::Client;
 use serde_json::Value;
 
 use crate::config::FigmaConfig;
 
+/// How long to wait for a Figma API response. Rendering large frames can take minutes, far longer
+/// than an image download should be allowed to stall.
+pub const DEFAULT_API_TIMEOUT: Duration = Duration::from_secs(300);
+
 pub struct FigmaImageExtractor {
   client: Client,
   config: FigmaConfig,
   api_url: String,
+  scale: f32,
+}
+
+/// An image node as found in the Figma document tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageNode {
+  pub id: String,
+  pub name: String,
+  /// Names of the ancestors of the node, starting from its page (e.g. `Page 1/Hero`).
+  pub page_path: String,
+  /// Width and height of the rendered area in points, when Figma reports them.
+  pub bounds: Option<(f64, f64)>,
+}
+
+/// A rendered image node together with its render URL.
+#[derive(Debug, Clone)]
+pub struct FigmaImage {
+  pub node: ImageNode,
+  pub url: Value,
+}
+
+#[derive(Debug, Clone)]
+pub struct FigmaImages {
+  pub file_version: Option<String>,
+  pub scale: f32,
+  pub images: Vec<FigmaImage>,
 }
 
 impl FigmaImageExtractor {
@@ -15,15 +47,22 @@ impl FigmaImageExtractor {
       client,
       config,
       api_url: "https://api.figma.com/v1".to_string(),
+      scale: 1.0,
     }
   }
 
+  pub fn with_scale(mut self, scale: f32) -> Self {
+    self.scale = scale;
+    self
+  }
+
   #[cfg(test)]
   fn with_api_url(client: Client, config: FigmaConfig, api_url: String) -> Self {
     Self {
       client,
       config,
       api_url,
+      scale: 1.0,
     }
   }
 
@@ -35,44 +74,77 @@ impl FigmaImageExtractor {
   }
 
   pub async fn extract(&self) -> Result<Vec<(String, Value, String)>, reqwest::Error> {
+    let images = self
+      .extract_images()
+      .await?
+      .images
+      .into_iter()
+      .map(|image| (image.node.id, image.url, image.node.name))
+      .collect();
+
+    Ok(images)
+  }
+
+  pub async fn extract_images(&self) -> Result<FigmaImages, reqwest::Error> {
+    let (file_version, image_nodes) = self.get_image_nodes().await?;
+    self.render_images(file_version, image_nodes).await
+  }
+
+  /// Asks Figma to render `image_nodes` and returns their render URLs.
+  #[tracing::instrument(name = "figma_api", skip_all, fields(endpoint = "images", nodes = image_nodes.len()))]
+  pub async fn render_images(
+    &self,
+    file_version: Option<String>,
+    image_nodes: Vec<ImageNode>,
+  ) -> Result<FigmaImages, reqwest::Error> {
     let file_url = self.build_url("images");
-    let image_nodes = self.get_image_nodes().await?;
     let ids = image_nodes
       .iter()
-      .map(|(id, _)| id.as_str())
+      .map(|node| node.id.as_str())
       .collect::<Vec<_>>()
       .join(",");
 
     let response = self
       .client
       .get(&file_url)
-      .query(&[("format", "png"), ("ids", &ids)])
+      .query(&[
+        ("format", "png"),
+        ("ids", &ids),
+        ("scale", &self.scale.to_string()),
+      ])
       .header("X-Figma-Token", &self.config.figma_access_token)
       .send()
       .await?
       .json::<Value>()
       .await?;
 
-    let images = response["images"]
+    let images: Vec<FigmaImage> = response["images"]
       .as_object()
       .map(|imgs| {
         imgs
           .iter()
           .filter_map(|(id, url)| {
-            let name = image_nodes
-              .iter()
-              .find(|(node_id, _)| node_id == id)
-              .map(|(_, name)| name.clone())?;
-            Some((id.clone(), url.clone(), name))
+            let node = image_nodes.iter().find(|node| &node.id == id)?.clone();
+            Some(FigmaImage {
+              node,
+              url: url.clone(),
+            })
           })
           .collect()
       })
       .unwrap_or_default();
 
-    Ok(images)
+    tracing::debug!(rendered = images.len(), "rendered images");
+    Ok(FigmaImages {
+      file_version,
+      scale: self.scale,
+      images,
+    })
   }
 
-  async fn get_image_nodes(&self) -> Result<Vec<(String, String)>, reqwest::Error> {
+  /// Fetches the file and returns its version along with every image node in it.
+  #[tracing::instrument(name = "figma_api", skip_all, fields(endpoint = "files"))]
+  pub async fn get_image_nodes(&self) -> Result<(Option<String>, Vec<ImageNode>), reqwest::Error> {
     let file_url = self.build_url("files");
     let response = self
       .client
@@ -83,27 +155,38 @@ impl FigmaImageExtractor {
       .json::<Value>()
       .await?;
 
+    let file_version = response["version"].as_str().map(str::to_string);
     let document = response
       .as_object()
       .and_then(|obj| obj.get("document"))
       .unwrap_or(&Value::Null);
 
     let image_nodes = Self::extract_image_nodes(document);
-    Ok(image_nodes)
+    tracing::debug!(
+      version = file_version.as_deref(),
+      nodes = image_nodes.len(),
+      "found image nodes"
+    );
+    Ok((file_version, image_nodes))
   }
 
-  fn extract_image_nodes(document: &Value) -> Vec<(String, String)> {
+  fn extract_image_nodes(document: &Value) -> Vec<ImageNode> {
     let mut image_nodes = Vec::new();
-    let mut stack = vec![document.clone()];
+    let mut stack = vec![(document, Vec::<&str>::new())];
 
-    while let Some(node) = stack.pop() {
-      if Self::is_image_node(&node) {
+    while let Some((node, ancestors)) = stack.pop() {
+      if Self::is_image_node(node) {
         if let Some((id, name)) = node
           .as_object()
           .and_then(|obj| Some((obj.get("id")?, obj.get("name")?)))
           .and_then(|(id, name)| Some((id.as_str()?, name.as_str()?)))
         {
-          image_nodes.push((id.to_string(), name.to_string()));
+          image_nodes.push(ImageNode {
+            id: id.to_string(),
+            name: name.to_string(),
+            page_path: ancestors.join("/"),
+            bounds: Self::node_bounds(node),
+          });
         }
       }
 
@@ -112,13 +195,32 @@ impl FigmaImageExtractor {
         .and_then(|obj| obj.get("children"))
         .and_then(|c| c.as_array())
       {
-        stack.extend(children.iter().cloned());
+        let mut path = ancestors.clone();
+        // The document root is not part of the page path.
+        if !std::ptr::eq(node, document) {
+          path.extend(node.get("name").and_then(|n| n.as_str()));
+        }
+        stack.extend(children.iter().map(|child| (child, path.clone())));
       }
     }
 
     image_nodes
   }
 
+  /// Renders cover `absoluteRenderBounds` (which includes effects such as shadows), falling back
+  /// to `absoluteBoundingBox` for files that predate it.
+  fn node_bounds(node: &Value) -> Option<(f64, f64)> {
+    ["absoluteRenderBounds", "absoluteBoundingBox"]
+      .iter()
+      .find_map(|key| {
+        let bounds = node.get(key)?;
+        Some((
+          bounds.get("width")?.as_f64()?,
+          bounds.get("height")?.as_f64()?,
+        ))
+      })
+  }
+
   fn is_image_node(node: &Value) -> bool {
     if let Some(obj) = node.as_object() {
       if obj.get("type").and_then(|t| t.as_str()) == Some("IMAGE") {
@@ -294,8 +396,107 @@ mod tests {
 
     let nodes = FigmaImageExtractor::extract_image_nodes(&document);
     assert_eq!(nodes.len(), 2);
-    assert_eq!(nodes[0], ("1:3".to_string(), "test_image2".to_string()));
-    assert_eq!(nodes[1], ("1:1".to_string(), "test_image1".to_string()));
+    assert_eq!(
+      (nodes[0].id.as_str(), nodes[0].name.as_str()),
+      ("1:3", "test_image2")
+    );
+    assert_eq!(
+      (nodes[1].id.as_str(), nodes[1].name.as_str()),
+      ("1:1", "test_image1")
+    );
+  }
+
+  #[test]
+  fn test_extract_image_nodes_page_path() {
+    let document = json!({
+        "id": "0:0",
+        "name": "Document",
+        "children": [{
+            "id": "0:1",
+            "type": "CANVAS",
+            "name": "Page 1",
+            "children": [{
+                "id": "1:2",
+                "type": "FRAME",
+                "name": "Hero",
+                "children": [{
+                    "id": "1:3",
+                    "type": "IMAGE",
+                    "name": "background",
+                    "absoluteBoundingBox": { "x": 0, "y": 0, "width": 120.0, "height": 80.5 }
+                }]
+            }]
+        }]
+    });
+
+    let nodes = FigmaImageExtractor::extract_image_nodes(&document);
+    assert_eq!(
+      nodes,
+      vec![ImageNode {
+        id: "1:3".to_string(),
+        name: "background".to_string(),
+        page_path: "Page 1/Hero".to_string(),
+        bounds: Some((120.0, 80.5)),
+      }]
+    );
+  }
+
+  #[tokio::test]
+  async fn test_extract_images() {
+    let server = MockServer::start();
+
+    server.mock(|when, then| {
+      when.method(GET).path("/files/test-key");
+      then
+        .status(200)
+        .header("content-type", "application/json")
+        .json_body(json!({
+            "version": "4242",
+            "document": {
+                "id": "0:0",
+                "children": [{
+                    "id": "0:1",
+                    "name": "Page 1",
+                    "children": [{
+                        "id": "1:1",
+                        "type": "IMAGE",
+                        "name": "test_image"
+                    }]
+                }]
+            }
+        }));
+    });
+
+    let images_mock = server.mock(|when, then| {
+      when
+        .method(GET)
+        .path("/images/test-key")
+        .query_param("ids", "1:1")
+        .query_param("scale", "2");
+      then
+        .status(200)
+        .header("content-type", "application/json")
+        .json_body(json!({
+            "images": {
+                "1:1": "https://example.com/test_image.png"
+            }
+        }));
+    });
+
+    let config = FigmaConfig {
+      figma_access_token: "test-token".to_string(),
+      figma_file_key: "test-key".to_string(),
+    };
+    let extractor =
+      FigmaImageExtractor::with_api_url(Client::new(), config, server.base_url()).with_scale(2.0);
+
+    let result = extractor.extract_images().await.unwrap();
+    images_mock.assert();
+
+    assert_eq!(result.file_version.as_deref(), Some("4242"));
+    assert_eq!(result.scale, 2.0);
+    assert_eq!(result.images.len(), 1);
+    assert_eq!(result.images[0].node.page_path, "Page 1");
   }
 
   #[tokio::test]
@@ -329,8 +530,11 @@ mod tests {
     };
     let extractor = FigmaImageExtractor::with_api_url(Client::new(), config, server.base_url());
 
-    let nodes = extractor.get_image_nodes().await.unwrap();
+    let (_, nodes) = extractor.get_image_nodes().await.unwrap();
     assert_eq!(nodes.len(), 1);
-    assert_eq!(nodes[0], ("1:1".to_string(), "test_image".to_string()));
+    assert_eq!(
+      (nodes[0].id.as_str(), nodes[0].name.as_str()),
+      ("1:1", "test_image")
+    );
   }
 }
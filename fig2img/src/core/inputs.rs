@@ -0,0 +1,357 @@
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::core::svg;
+
+/// What happens to symbolic links found in the input directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SymlinkPolicy {
+  /// Converts the files links point to and descends into linked directories. Loops are reported
+  /// and skipped.
+  #[default]
+  Follow,
+  /// Leaves links out.
+  Skip,
+}
+
+impl FromStr for SymlinkPolicy {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_ascii_lowercase().as_str() {
+      "follow" => Ok(Self::Follow),
+      "skip" => Ok(Self::Skip),
+      _ => Err(format!("Unsupported symlink policy: {}", s)),
+    }
+  }
+}
+
+impl fmt::Display for SymlinkPolicy {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Self::Follow => "follow",
+      Self::Skip => "skip",
+    })
+  }
+}
+
+/// Which files in the input directory are converted.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct InputOptions {
+  /// Also looks in subdirectories.
+  recursive: bool,
+  /// Globs that paths relative to the input directory must match; none matches everything. A
+  /// glob without a `/` is matched against the file name at any depth.
+  include: Vec<String>,
+  /// Globs for files and directories to leave out, matched like `include`.
+  exclude: Vec<String>,
+  symlinks: SymlinkPolicy,
+}
+
+/// Files found in the input directory.
+#[derive(Debug, Default)]
+pub struct Inputs {
+  pub files: Vec<PathBuf>,
+  /// Files that were found but can't be converted, with the reason.
+  pub skipped: Vec<(PathBuf, String)>,
+}
+
+impl InputOptions {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn recursive(mut self, recursive: bool) -> Self {
+    self.recursive = recursive;
+    self
+  }
+
+  pub fn include(mut self, include: Vec<String>) -> Self {
+    self.include = include;
+    self
+  }
+
+  pub fn exclude(mut self, exclude: Vec<String>) -> Self {
+    self.exclude = exclude;
+    self
+  }
+
+  pub fn symlinks(mut self, symlinks: SymlinkPolicy) -> Self {
+    self.symlinks = symlinks;
+    self
+  }
+
+  pub fn validate(&self) -> Result<(), Error> {
+    glob_set(&self.include)?;
+    glob_set(&self.exclude)?;
+    Ok(())
+  }
+
+  /// Lists the images in `dir` that can be decoded or rasterized, in file name order. Formats are
+  /// detected from the content, so extensions don't matter. `output_dir` is left out when it's
+  /// inside `dir`, so earlier outputs aren't converted again.
+  pub fn find(&self, dir: &Path, output_dir: Option<&Path>) -> Result<Inputs, Error> {
+    let include = glob_set(&self.include)?;
+    let exclude = glob_set(&self.exclude)?;
+    let relative = |path: &Path| path.strip_prefix(dir).unwrap_or(path).to_path_buf();
+    let nested_output = output_dir.and_then(|output_dir| nested_dir(dir, output_dir));
+
+    let walker = WalkDir::new(dir)
+      .min_depth(1)
+      .max_depth(if self.recursive { usize::MAX } else { 1 })
+      .follow_links(self.symlinks == SymlinkPolicy::Follow)
+      .sort_by_file_name()
+      .into_iter()
+      .filter_entry(|entry| {
+        !(entry.file_type().is_dir()
+          && (exclude.is_match(relative(entry.path()))
+            || nested_output.as_deref() == Some(entry.path())))
+      });
+
+    let mut inputs = Inputs::default();
+    for entry in walker {
+      let entry = match entry {
+        Ok(entry) => entry,
+        // The input directory itself can't be read.
+        Err(e) if e.depth() == 0 => return Err(e.into()),
+        Err(e) => {
+          let path = e.path().map(Path::to_path_buf).unwrap_or_default();
+          inputs.skipped.push((path, e.to_string()));
+          continue;
+        }
+      };
+
+      let path = entry.path();
+      if entry.path_is_symlink() && self.symlinks == SymlinkPolicy::Skip {
+        inputs
+          .skipped
+          .push((path.to_path_buf(), "symbolic link".to_string()));
+        continue;
+      }
+      if !entry.file_type().is_file() {
+        continue;
+      }
+
+      let relative_path = relative(path);
+      if exclude.is_match(&relative_path)
+        || (!self.include.is_empty() && !include.is_match(&relative_path))
+      {
+        continue;
+      }
+
+      let header = svg::read_header(path).unwrap_or_default();
+      match image::guess_format(&header) {
+        Ok(format) if format.reading_enabled() => {}
+        _ if svg::is_svg(path, &header) => {}
+        Ok(format) => {
+          let reason = format!("{:?} images can't be decoded", format);
+          inputs.skipped.push((path.to_path_buf(), reason));
+          continue;
+        }
+        Err(_) => {
+          let reason = "not a supported image".to_string();
+          inputs.skipped.push((path.to_path_buf(), reason));
+          continue;
+        }
+      }
+
+      inputs.files.push(path.to_path_buf());
+    }
+
+    Ok(inputs)
+  }
+}
+
+/// Where `inner` is within `dir`, spelled as a path under `dir`, or `None` when it's elsewhere or
+/// `dir` itself.
+fn nested_dir(dir: &Path, inner: &Path) -> Option<PathBuf> {
+  let relative = inner
+    .canonicalize()
+    .ok()?
+    .strip_prefix(dir.canonicalize().ok()?)
+    .ok()?
+    .to_path_buf();
+  (!relative.as_os_str().is_empty()).then(|| dir.join(relative))
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet, Error> {
+  let mut builder = GlobSetBuilder::new();
+  for pattern in patterns {
+    let pattern = match pattern.contains('/') {
+      true => pattern.trim_start_matches("./").to_string(),
+      false => format!("**/{}", pattern),
+    };
+    let glob = GlobBuilder::new(&pattern)
+      .literal_separator(true)
+      .build()
+      .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+    builder.add(glob);
+  }
+  builder
+    .build()
+    .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+  use image::ImageFormat;
+  use tempfile::tempdir;
+
+  use super::*;
+
+  /// Writes a small PNG to `relative_path`, whatever its extension.
+  fn touch(dir: &Path, relative_path: &str) {
+    let path = dir.join(relative_path);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    image::RgbaImage::new(1, 1)
+      .save_with_format(path, ImageFormat::Png)
+      .unwrap();
+  }
+
+  fn found(options: &InputOptions, dir: &Path) -> Vec<String> {
+    options
+      .find(dir, None)
+      .unwrap()
+      .files
+      .iter()
+      .map(|path| {
+        let relative = path.strip_prefix(dir).unwrap();
+        relative.to_string_lossy().replace('\\', "/")
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_find_top_level_or_recursive() {
+    let dir = tempdir().unwrap();
+    for path in ["b.png", "a.PNG", "icons/c.png", "icons/small/d.png"] {
+      touch(dir.path(), path);
+    }
+    std::fs::write(dir.path().join("notes.txt"), "not an image").unwrap();
+
+    let top_level = InputOptions::new().find(dir.path(), None).unwrap();
+    assert_eq!(found(&InputOptions::new(), dir.path()), ["a.PNG", "b.png"]);
+    assert_eq!(top_level.skipped.len(), 1);
+
+    assert_eq!(
+      found(&InputOptions::new().recursive(true), dir.path()),
+      ["a.PNG", "b.png", "icons/c.png", "icons/small/d.png"]
+    );
+  }
+
+  #[test]
+  fn test_find_detects_formats_from_content() {
+    let dir = tempdir().unwrap();
+    let image = image::RgbaImage::new(2, 2);
+    for (name, format) in [
+      ("banner.bmp", ImageFormat::Bmp),
+      ("loader.gif", ImageFormat::Gif),
+      ("photo", ImageFormat::Jpeg),
+      ("scan.tif", ImageFormat::Tiff),
+      ("sticker.webp", ImageFormat::WebP),
+    ] {
+      let image = match format {
+        ImageFormat::Jpeg => image::DynamicImage::ImageRgba8(image.clone())
+          .to_rgb8()
+          .into(),
+        _ => image::DynamicImage::ImageRgba8(image.clone()),
+      };
+      image
+        .save_with_format(dir.path().join(name), format)
+        .unwrap();
+    }
+    std::fs::write(dir.path().join("fake.png"), "<html></html>").unwrap();
+    std::fs::write(
+      dir.path().join("logo.svg"),
+      r#"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="4"/>"#,
+    )
+    .unwrap();
+
+    let inputs = InputOptions::new().find(dir.path(), None).unwrap();
+
+    assert_eq!(
+      found(&InputOptions::new(), dir.path()),
+      [
+        "banner.bmp",
+        "loader.gif",
+        "logo.svg",
+        "photo",
+        "scan.tif",
+        "sticker.webp"
+      ]
+    );
+    assert_eq!(
+      inputs.skipped,
+      [(
+        dir.path().join("fake.png"),
+        "not a supported image".to_string()
+      )]
+    );
+  }
+
+  #[test]
+  fn test_find_with_include_and_exclude_globs() {
+    let dir = tempdir().unwrap();
+    for path in [
+      "hero.png",
+      "icon-add.png",
+      "icons/icon-close.png",
+      "drafts/icon-old.png",
+    ] {
+      touch(dir.path(), path);
+    }
+    let options = InputOptions::new().recursive(true);
+
+    assert_eq!(
+      found(&options.clone().include(vec!["icon-*".into()]), dir.path()),
+      [
+        "drafts/icon-old.png",
+        "icon-add.png",
+        "icons/icon-close.png"
+      ]
+    );
+    assert_eq!(
+      found(
+        &options.clone().include(vec!["icons/*.png".into()]),
+        dir.path()
+      ),
+      ["icons/icon-close.png"]
+    );
+    assert_eq!(
+      found(
+        &options
+          .clone()
+          .exclude(vec!["drafts".into(), "hero.png".into()]),
+        dir.path()
+      ),
+      ["icon-add.png", "icons/icon-close.png"]
+    );
+    assert!(options.include(vec!["[".into()]).validate().is_err());
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn test_symlink_policy() {
+    let dir = tempdir().unwrap();
+    let elsewhere = tempdir().unwrap();
+    touch(elsewhere.path(), "shared/logo.png");
+    std::os::unix::fs::symlink(elsewhere.path().join("shared"), dir.path().join("shared")).unwrap();
+    // A link back to the input directory must not loop forever.
+    std::os::unix::fs::symlink(dir.path(), dir.path().join("loop")).unwrap();
+
+    let options = InputOptions::new().recursive(true);
+    assert_eq!(found(&options, dir.path()), ["shared/logo.png"]);
+
+    let skipping = options.symlinks(SymlinkPolicy::Skip);
+    assert!(found(&skipping, dir.path()).is_empty());
+    assert_eq!(skipping.find(dir.path(), None).unwrap().skipped.len(), 2);
+  }
+}
@@ -0,0 +1,290 @@
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::utils::hash;
+
+pub const MANIFEST_FILENAME: &str = "manifest";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ManifestFormat {
+  #[default]
+  Json,
+  Toml,
+  Yaml,
+}
+
+impl ManifestFormat {
+  pub const ALL: [ManifestFormat; 3] = [Self::Json, Self::Toml, Self::Yaml];
+
+  pub fn extension(&self) -> &'static str {
+    match self {
+      Self::Json => "json",
+      Self::Toml => "toml",
+      Self::Yaml => "yaml",
+    }
+  }
+}
+
+impl FromStr for ManifestFormat {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_ascii_lowercase().as_str() {
+      "json" => Ok(Self::Json),
+      "toml" => Ok(Self::Toml),
+      "yaml" | "yml" => Ok(Self::Yaml),
+      _ => Err(format!("Unsupported manifest format: {}", s)),
+    }
+  }
+}
+
+impl fmt::Display for ManifestFormat {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.extension())
+  }
+}
+
+/// Machine-readable record of every asset produced by a run.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct AssetManifest {
+  pub generator: String,
+  pub assets: Vec<AssetEntry>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct AssetEntry {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub node_id: Option<String>,
+  pub name: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub page_path: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub file_version: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub scale: Option<f32>,
+  /// Render URL for downloads, input file for conversions.
+  pub source: String,
+  pub output_path: PathBuf,
+  pub width: u32,
+  pub height: u32,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub source_bytes: Option<u64>,
+  pub output_bytes: u64,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub source_format: Option<String>,
+  pub output_format: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub source_hash: Option<String>,
+  pub output_hash: String,
+  /// The higher-scale download this file was downsampled from, rather than rendered by Figma.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub derived_from: Option<PathBuf>,
+}
+
+impl AssetEntry {
+  /// Describes the file at `output_path`: dimensions, size, format and content hash.
+  pub async fn describe(name: &str, source: &str, output_path: &Path) -> Result<Self, Error> {
+    let bytes = fs::read(output_path).await?;
+    let (width, height) = image_dimensions(&bytes);
+
+    Ok(Self {
+      name: name.to_string(),
+      source: source.to_string(),
+      output_path: output_path.to_path_buf(),
+      width,
+      height,
+      output_bytes: bytes.len() as u64,
+      output_format: file_format(output_path),
+      output_hash: hash::content_hash(&bytes),
+      ..Default::default()
+    })
+  }
+
+  /// Fills in the source size, format and hash from the file at `source_path`. Dimensions are
+  /// taken from the source when they are still unknown, e.g. for an AVIF output that wasn't
+  /// resized.
+  pub async fn with_source_file(mut self, source_path: &Path) -> Result<Self, Error> {
+    let bytes = fs::read(source_path).await?;
+
+    if (self.width, self.height) == (0, 0) {
+      (self.width, self.height) = image_dimensions(&bytes);
+    }
+
+    self.source_bytes = Some(bytes.len() as u64);
+    self.source_format = Some(file_format(source_path));
+    self.source_hash = Some(hash::content_hash(&bytes));
+    Ok(self)
+  }
+}
+
+impl AssetManifest {
+  pub fn new(assets: Vec<AssetEntry>) -> Self {
+    Self {
+      generator: format!("fig2img {}", env!("CARGO_PKG_VERSION")),
+      assets,
+    }
+  }
+
+  pub fn render(&self, format: ManifestFormat) -> Result<String, Error> {
+    let invalid = |e: &dyn fmt::Display| Error::new(ErrorKind::InvalidData, e.to_string());
+
+    match format {
+      ManifestFormat::Json => serde_json::to_string_pretty(self).map_err(|e| invalid(&e)),
+      ManifestFormat::Toml => toml::to_string_pretty(self).map_err(|e| invalid(&e)),
+      ManifestFormat::Yaml => serde_yaml::to_string(self).map_err(|e| invalid(&e)),
+    }
+  }
+
+  pub fn parse(content: &str, format: ManifestFormat) -> Result<Self, Error> {
+    let invalid = |e: &dyn fmt::Display| Error::new(ErrorKind::InvalidData, e.to_string());
+
+    match format {
+      ManifestFormat::Json => serde_json::from_str(content).map_err(|e| invalid(&e)),
+      ManifestFormat::Toml => toml::from_str(content).map_err(|e| invalid(&e)),
+      ManifestFormat::Yaml => serde_yaml::from_str(content).map_err(|e| invalid(&e)),
+    }
+  }
+
+  pub async fn write(&self, dir: &Path, format: ManifestFormat) -> Result<PathBuf, Error> {
+    let path = dir.join(format!("{}.{}", MANIFEST_FILENAME, format.extension()));
+
+    fs::write(&path, self.render(format)?).await?;
+    Ok(path)
+  }
+
+  /// Reads the manifest written to `dir` by a previous run, in whichever format it was written.
+  pub async fn read(dir: &Path) -> Option<Self> {
+    for format in ManifestFormat::ALL {
+      let path = dir.join(format!("{}.{}", MANIFEST_FILENAME, format.extension()));
+      if let Ok(content) = fs::read_to_string(&path).await {
+        return Self::parse(&content, format).ok();
+      }
+    }
+    None
+  }
+
+  /// Finds the entry whose output file is at the same path relative to `dir`, the directory this
+  /// manifest was read from, as `path`. Outputs recorded elsewhere, e.g. before the directory was
+  /// moved, are compared by file name.
+  pub fn find_by_output(&self, dir: &Path, path: &Path) -> Option<&AssetEntry> {
+    let relative_path = hash::relative_name(dir, path)?;
+    self
+      .assets
+      .iter()
+      .find(|asset| hash::relative_name(dir, &asset.output_path).as_ref() == Some(&relative_path))
+  }
+}
+
+fn image_dimensions(bytes: &[u8]) -> (u32, u32) {
+  image::ImageReader::new(std::io::Cursor::new(bytes))
+    .with_guessed_format()
+    .ok()
+    .and_then(|reader| reader.into_dimensions().ok())
+    .unwrap_or_default()
+}
+
+fn file_format(path: &Path) -> String {
+  path
+    .extension()
+    .and_then(|ext| ext.to_str())
+    .map(str::to_ascii_lowercase)
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use tempfile::tempdir;
+
+  use super::*;
+
+  fn sample_manifest() -> AssetManifest {
+    AssetManifest::new(vec![AssetEntry {
+      node_id: Some("1:1".to_string()),
+      name: "hero".to_string(),
+      page_path: Some("Page 1/Landing".to_string()),
+      file_version: Some("4242".to_string()),
+      scale: Some(2.0),
+      source: "downloads/hero.png".to_string(),
+      output_path: PathBuf::from("output/hero.webp"),
+      width: 200,
+      height: 100,
+      source_bytes: Some(2048),
+      output_bytes: 512,
+      source_format: Some("png".to_string()),
+      output_format: "webp".to_string(),
+      source_hash: Some("aa".to_string()),
+      output_hash: "bb".to_string(),
+      derived_from: None,
+    }])
+  }
+
+  #[test]
+  fn test_manifest_format_from_str() {
+    assert_eq!("json".parse(), Ok(ManifestFormat::Json));
+    assert_eq!("TOML".parse(), Ok(ManifestFormat::Toml));
+    assert_eq!("yml".parse(), Ok(ManifestFormat::Yaml));
+    assert!("xml".parse::<ManifestFormat>().is_err());
+  }
+
+  #[test]
+  fn test_manifest_round_trip() {
+    let manifest = sample_manifest();
+
+    for format in ManifestFormat::ALL {
+      let content = manifest.render(format).unwrap();
+      let parsed = AssetManifest::parse(&content, format).unwrap();
+
+      assert_eq!(parsed.assets.len(), 1);
+      assert_eq!(parsed.assets[0].node_id.as_deref(), Some("1:1"));
+      assert_eq!(
+        parsed.assets[0].output_path,
+        PathBuf::from("output/hero.webp")
+      );
+    }
+  }
+
+  #[tokio::test]
+  async fn test_describe_and_read() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().join("hero.png");
+    image::RgbaImage::new(3, 2).save(&path).unwrap();
+
+    let entry = AssetEntry::describe("hero", "https://example.com/hero.png", &path)
+      .await
+      .unwrap();
+
+    assert_eq!((entry.width, entry.height), (3, 2));
+    assert_eq!(entry.output_format, "png");
+    assert_eq!(entry.output_bytes, std::fs::metadata(&path).unwrap().len());
+
+    let avif_path = temp_dir.path().join("hero.avif");
+    std::fs::write(&avif_path, b"not inspectable").unwrap();
+    let converted = AssetEntry::describe("hero", "hero.png", &avif_path)
+      .await
+      .unwrap()
+      .with_source_file(&path)
+      .await
+      .unwrap();
+    assert_eq!((converted.width, converted.height), (3, 2));
+
+    AssetManifest::new(vec![entry])
+      .write(temp_dir.path(), ManifestFormat::Yaml)
+      .await
+      .unwrap();
+
+    let manifest = AssetManifest::read(temp_dir.path()).await.unwrap();
+    assert!(manifest.find_by_output(temp_dir.path(), &path).is_some());
+    assert!(manifest
+      .find_by_output(temp_dir.path(), &temp_dir.path().join("icons/hero.png"))
+      .is_none());
+    assert!(manifest
+      .find_by_output(Path::new("moved"), Path::new("moved/hero.png"))
+      .is_some());
+  }
+}
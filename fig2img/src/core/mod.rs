@@ -1,3 +1,9 @@
 pub mod converter;
+pub mod density;
 pub mod downloader;
 pub mod extractor;
+pub mod inputs;
+pub mod manifest;
+pub mod resize;
+pub mod svg;
+pub mod validator;
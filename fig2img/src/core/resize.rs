@@ -0,0 +1,463 @@
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+
+use image::imageops::FilterType;
+use image::{ColorType, DynamicImage, Rgba, Rgba32FImage};
+use serde::{Deserialize, Serialize};
+
+/// How an image is fitted into a target width and height.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+  /// Scales to fit inside the box, keeping the aspect ratio.
+  #[default]
+  Contain,
+  /// Scales to fill the box, keeping the aspect ratio, and crops the overflow around the center.
+  Cover,
+  /// Stretches to exactly the box.
+  Exact,
+}
+
+impl FromStr for Fit {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_ascii_lowercase().as_str() {
+      "contain" => Ok(Self::Contain),
+      "cover" => Ok(Self::Cover),
+      "exact" => Ok(Self::Exact),
+      _ => Err(format!("Unsupported fit: {}", s)),
+    }
+  }
+}
+
+impl fmt::Display for Fit {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Self::Contain => "contain",
+      Self::Cover => "cover",
+      Self::Exact => "exact",
+    })
+  }
+}
+
+/// Resampling filter used when resizing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResizeFilter {
+  /// Sharpest, best for downscaling detailed artwork.
+  #[default]
+  Lanczos3,
+  /// Slightly softer with less ringing around hard edges.
+  CatmullRom,
+}
+
+impl ResizeFilter {
+  fn filter_type(self) -> FilterType {
+    match self {
+      Self::Lanczos3 => FilterType::Lanczos3,
+      Self::CatmullRom => FilterType::CatmullRom,
+    }
+  }
+}
+
+impl FromStr for ResizeFilter {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_ascii_lowercase().replace('_', "-").as_str() {
+      "lanczos3" => Ok(Self::Lanczos3),
+      "catmull-rom" | "catmullrom" => Ok(Self::CatmullRom),
+      _ => Err(format!("Unsupported resize filter: {}", s)),
+    }
+  }
+}
+
+impl fmt::Display for ResizeFilter {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Self::Lanczos3 => "lanczos3",
+      Self::CatmullRom => "catmull-rom",
+    })
+  }
+}
+
+/// Sizes to produce for each input. With `widths` or `heights`, every input becomes one output
+/// per size; `max_dimension` alone shrinks oversized inputs and keeps their names.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ResizeOptions {
+  widths: Vec<u32>,
+  /// Paired with `widths` by position when both are given; a single value applies to all.
+  heights: Vec<u32>,
+  /// Upper bound for the longer side of every output.
+  max_dimension: Option<u32>,
+  fit: Fit,
+  filter: ResizeFilter,
+  /// Allows outputs larger than the input. Otherwise those sizes are skipped.
+  upscale: bool,
+}
+
+impl ResizeOptions {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn widths(mut self, widths: Vec<u32>) -> Self {
+    self.widths = widths;
+    self
+  }
+
+  pub fn heights(mut self, heights: Vec<u32>) -> Self {
+    self.heights = heights;
+    self
+  }
+
+  pub fn max_dimension(mut self, max_dimension: Option<u32>) -> Self {
+    self.max_dimension = max_dimension;
+    self
+  }
+
+  pub fn fit(mut self, fit: Fit) -> Self {
+    self.fit = fit;
+    self
+  }
+
+  pub fn filter(mut self, filter: ResizeFilter) -> Self {
+    self.filter = filter;
+    self
+  }
+
+  pub fn upscale(mut self, upscale: bool) -> Self {
+    self.upscale = upscale;
+    self
+  }
+
+  /// Whether any resizing was asked for.
+  pub fn is_enabled(&self) -> bool {
+    !self.widths.is_empty() || !self.heights.is_empty() || self.max_dimension.is_some()
+  }
+
+  /// Number of outputs per input and format, before sizes that would upscale are skipped.
+  pub fn variant_count(&self) -> usize {
+    self.targets().len().max(1)
+  }
+
+  pub fn validate(&self) -> Result<(), Error> {
+    let (widths, heights) = (self.widths.len(), self.heights.len());
+    if widths > 1 && heights > 1 && widths != heights {
+      return Err(Error::new(
+        ErrorKind::InvalidInput,
+        format!(
+          "Got {} widths and {} heights; give the same number of each, or a single one",
+          widths, heights
+        ),
+      ));
+    }
+    let sizes = self
+      .widths
+      .iter()
+      .chain(&self.heights)
+      .chain(&self.max_dimension);
+    if sizes.into_iter().any(|&size| size == 0) {
+      return Err(Error::new(
+        ErrorKind::InvalidInput,
+        "Sizes must be at least 1",
+      ));
+    }
+    Ok(())
+  }
+
+  /// The requested boxes, from `widths` and `heights`.
+  fn targets(&self) -> Vec<(Option<u32>, Option<u32>)> {
+    let count = self.widths.len().max(self.heights.len());
+    let pick = |sizes: &[u32], i: usize| match sizes.len() {
+      0 => None,
+      1 => Some(sizes[0]),
+      _ => sizes.get(i).copied(),
+    };
+
+    (0..count)
+      .map(|i| (pick(&self.widths, i), pick(&self.heights, i)))
+      .collect()
+  }
+
+  /// Works out the outputs for an input of `width` x `height`. Sizes that would upscale are
+  /// returned separately, described as in their file name; if all of them would, the input is
+  /// kept at its own size instead.
+  pub fn plan(&self, width: u32, height: u32) -> (Vec<Variant>, Vec<String>) {
+    let targets = self.targets();
+    if targets.is_empty() {
+      let (width, height) = self.cap(width, height);
+      return (
+        vec![Variant {
+          suffix: String::new(),
+          width,
+          height,
+          crop: false,
+        }],
+        Vec::new(),
+      );
+    }
+
+    let mut variants = Vec::<Variant>::new();
+    let mut skipped = Vec::new();
+    for target in &targets {
+      let variant = self.variant(width, height, *target);
+      if !self.upscale && (variant.scale_x(width) > 1.0 || variant.scale_y(height) > 1.0) {
+        skipped.push((*target, variant));
+      } else if variants.iter().all(|other| other.suffix != variant.suffix) {
+        variants.push(variant);
+      }
+    }
+
+    if variants.is_empty() {
+      // It stands in for the smallest size, which is no longer reported as skipped.
+      let smallest = (0..skipped.len())
+        .min_by_key(|&i| u64::from(skipped[i].1.width) * u64::from(skipped[i].1.height))
+        .unwrap_or_default();
+      let (kind, _) = skipped.remove(smallest);
+      let (width, height) = self.cap(width, height);
+      let target = (kind.0.map(|_| width), kind.1.map(|_| height));
+      variants.push(Variant {
+        crop: false,
+        ..self.variant(width, height, target)
+      });
+    }
+    let skipped = skipped
+      .into_iter()
+      .map(|(_, variant)| variant.suffix.trim_start_matches('-').to_string())
+      .collect();
+    (variants, skipped)
+  }
+
+  fn variant(&self, width: u32, height: u32, target: (Option<u32>, Option<u32>)) -> Variant {
+    let scaled = |size: u32, from: u32, to: u32| {
+      ((f64::from(size) * f64::from(to) / f64::from(from)).round() as u32).max(1)
+    };
+
+    let (out_width, out_height, crop) = match target {
+      (Some(w), None) => (w, scaled(height, width, w), false),
+      (None, Some(h)) => (scaled(width, height, h), h, false),
+      (Some(w), Some(h)) => match self.fit {
+        Fit::Contain if u64::from(w) * u64::from(height) <= u64::from(h) * u64::from(width) => {
+          (w, scaled(height, width, w), false)
+        }
+        Fit::Contain => (scaled(width, height, h), h, false),
+        Fit::Cover => (w, h, true),
+        Fit::Exact => (w, h, false),
+      },
+      (None, None) => (width, height, false),
+    };
+
+    let (out_width, out_height) = self.cap(out_width, out_height);
+    let suffix = match target {
+      (Some(_), None) => format!("-{}w", out_width),
+      (None, Some(_)) => format!("-{}h", out_height),
+      _ => format!("-{}x{}", out_width, out_height),
+    };
+
+    Variant {
+      suffix,
+      width: out_width,
+      height: out_height,
+      crop,
+    }
+  }
+
+  /// Shrinks `width` x `height` to fit `max_dimension`, keeping the aspect ratio.
+  fn cap(&self, width: u32, height: u32) -> (u32, u32) {
+    match self.max_dimension {
+      Some(max) if width.max(height) > max => {
+        let scale = f64::from(max) / f64::from(width.max(height));
+        (
+          ((f64::from(width) * scale).round() as u32).max(1),
+          ((f64::from(height) * scale).round() as u32).max(1),
+        )
+      }
+      _ => (width, height),
+    }
+  }
+
+  pub fn apply(&self, image: &DynamicImage, variant: &Variant) -> DynamicImage {
+    let filter = self.filter.filter_type();
+    let resize = |image: &DynamicImage| match variant.crop {
+      true => image.resize_to_fill(variant.width, variant.height, filter),
+      false => image.resize_exact(variant.width, variant.height, filter),
+    };
+    if !image.color().has_alpha() {
+      return resize(image);
+    }
+
+    let premultiplied = DynamicImage::ImageRgba32F(premultiply(image.to_rgba32f()));
+    unpremultiply(resize(&premultiplied).into_rgba32f(), image.color())
+  }
+}
+
+/// Scales color by alpha, so the hidden color of transparent pixels doesn't bleed into the edges
+/// when resampling.
+fn premultiply(mut image: Rgba32FImage) -> Rgba32FImage {
+  for Rgba([r, g, b, a]) in image.pixels_mut() {
+    (*r, *g, *b) = (*r * *a, *g * *a, *b * *a);
+  }
+  image
+}
+
+/// Undoes `premultiply`, converting back to `color`.
+fn unpremultiply(mut image: Rgba32FImage, color: ColorType) -> DynamicImage {
+  for Rgba(pixel) in image.pixels_mut() {
+    let alpha = pixel[3].clamp(0.0, 1.0);
+    *pixel = match alpha {
+      0.0 => [0.0; 4],
+      _ => [
+        (pixel[0] / alpha).clamp(0.0, 1.0),
+        (pixel[1] / alpha).clamp(0.0, 1.0),
+        (pixel[2] / alpha).clamp(0.0, 1.0),
+        alpha,
+      ],
+    };
+  }
+
+  let image = DynamicImage::ImageRgba32F(image);
+  match color {
+    ColorType::La8 => DynamicImage::ImageLumaA8(image.to_luma_alpha8()),
+    ColorType::La16 => DynamicImage::ImageLumaA16(image.to_luma_alpha16()),
+    ColorType::Rgba16 => DynamicImage::ImageRgba16(image.to_rgba16()),
+    ColorType::Rgba32F => image,
+    _ => DynamicImage::ImageRgba8(image.to_rgba8()),
+  }
+}
+
+/// One output size of an input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variant {
+  /// Appended to the file stem, e.g. `-640w`. Empty when the output keeps the input's name.
+  pub suffix: String,
+  pub width: u32,
+  pub height: u32,
+  /// Whether the image is cropped to this aspect ratio rather than stretched.
+  crop: bool,
+}
+
+impl Variant {
+  /// Whether this is the input unchanged.
+  pub fn is_original(&self, width: u32, height: u32) -> bool {
+    (self.width, self.height) == (width, height)
+  }
+
+  fn scale_x(&self, width: u32) -> f64 {
+    f64::from(self.width) / f64::from(width)
+  }
+
+  fn scale_y(&self, height: u32) -> f64 {
+    f64::from(self.height) / f64::from(height)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use image::{GenericImageView, RgbaImage};
+
+  use super::*;
+
+  fn sizes(variants: &[Variant]) -> Vec<(&str, u32, u32)> {
+    variants
+      .iter()
+      .map(|v| (v.suffix.as_str(), v.width, v.height))
+      .collect()
+  }
+
+  #[test]
+  fn test_widths_skip_upscaling() {
+    let options = ResizeOptions::new().widths(vec![320, 640, 1280]);
+
+    let (variants, skipped) = options.plan(1000, 500);
+    assert_eq!(sizes(&variants), [("-320w", 320, 160), ("-640w", 640, 320)]);
+    assert_eq!(skipped, ["1280w"]);
+
+    let (variants, skipped) = options.clone().upscale(true).plan(1000, 500);
+    assert_eq!(variants.len(), 3);
+    assert!(skipped.is_empty());
+
+    let (variants, skipped) = options.plan(200, 100);
+    assert_eq!(sizes(&variants), [("-200w", 200, 100)]);
+    assert_eq!(skipped, ["640w", "1280w"]);
+
+    // The kept size stands in for the smallest width, whatever order they were given in.
+    let (variants, skipped) = ResizeOptions::new().widths(vec![1280, 640]).plan(200, 100);
+    assert_eq!(sizes(&variants), [("-200w", 200, 100)]);
+    assert_eq!(skipped, ["1280w"]);
+  }
+
+  #[test]
+  fn test_fit_modes() {
+    let options = |fit| {
+      ResizeOptions::new()
+        .widths(vec![100])
+        .heights(vec![100])
+        .fit(fit)
+    };
+
+    let (contain, _) = options(Fit::Contain).plan(400, 200);
+    assert_eq!(sizes(&contain), [("-100x50", 100, 50)]);
+    let (cover, _) = options(Fit::Cover).plan(400, 200);
+    assert_eq!(sizes(&cover), [("-100x100", 100, 100)]);
+
+    let image = DynamicImage::ImageRgba8(RgbaImage::new(400, 200));
+    for fit in [Fit::Cover, Fit::Exact] {
+      let options = options(fit);
+      let (variants, _) = options.plan(400, 200);
+      assert_eq!(options.apply(&image, &variants[0]).dimensions(), (100, 100));
+    }
+  }
+
+  #[test]
+  fn test_transparent_color_does_not_bleed_into_edges() {
+    // An opaque blue square on a border that is transparent but red underneath.
+    let image = RgbaImage::from_fn(12, 12, |x, y| {
+      match (4..8).contains(&x) && (4..8).contains(&y) {
+        true => Rgba([0, 0, 255, 255]),
+        false => Rgba([255, 0, 0, 0]),
+      }
+    });
+    let options = ResizeOptions::new().widths(vec![6]);
+    let (variants, _) = options.plan(12, 12);
+
+    let resized = options.apply(&DynamicImage::ImageRgba8(image), &variants[0]);
+    assert_eq!(resized.color(), ColorType::Rgba8);
+    for (_, _, Rgba([r, _, b, a])) in resized.pixels() {
+      if a > 0 {
+        assert!(r <= 1 && b >= 254, "fringe {:?}", [r, b, a]);
+      }
+    }
+  }
+
+  #[test]
+  fn test_max_dimension() {
+    let options = ResizeOptions::new().max_dimension(Some(500));
+
+    let (variants, _) = options.plan(1000, 400);
+    assert_eq!(sizes(&variants), [("", 500, 200)]);
+    assert!(options.plan(300, 200).0[0].is_original(300, 200));
+
+    let (variants, _) = options.widths(vec![800]).upscale(true).plan(1000, 400);
+    assert_eq!(sizes(&variants), [("-500w", 500, 200)]);
+  }
+
+  #[test]
+  fn test_validate() {
+    assert!(ResizeOptions::new().validate().is_ok());
+    assert!(ResizeOptions::new()
+      .widths(vec![320, 640])
+      .heights(vec![200])
+      .validate()
+      .is_ok());
+    assert!(ResizeOptions::new()
+      .widths(vec![320, 640])
+      .heights(vec![1, 2, 3])
+      .validate()
+      .is_err());
+    assert!(ResizeOptions::new().widths(vec![0]).validate().is_err());
+  }
+}
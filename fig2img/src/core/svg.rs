@@ -0,0 +1,325 @@
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use image::{DynamicImage, RgbaImage};
+use resvg::tiny_skia::{Pixmap, Transform};
+use resvg::usvg;
+use serde::{Deserialize, Serialize};
+
+/// Largest width or height an SVG is rasterized at, to keep a typo from allocating gigabytes.
+pub const MAX_DIMENSION: u32 = 16384;
+
+/// How SVG inputs are rasterized.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SvgOptions {
+  /// Width to rasterize at. With `height` too, the image is fitted inside both, keeping its
+  /// aspect ratio.
+  width: Option<u32>,
+  height: Option<u32>,
+  /// Multiplies the size the SVG declares, when no width or height is given.
+  scale: f32,
+  /// Directories searched for the fonts used by text, before the system fonts.
+  font_dirs: Vec<PathBuf>,
+  system_fonts: bool,
+}
+
+impl Default for SvgOptions {
+  fn default() -> Self {
+    Self {
+      width: None,
+      height: None,
+      scale: 1.0,
+      font_dirs: Vec::new(),
+      system_fonts: true,
+    }
+  }
+}
+
+impl SvgOptions {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn width(mut self, width: Option<u32>) -> Self {
+    self.width = width;
+    self
+  }
+
+  pub fn height(mut self, height: Option<u32>) -> Self {
+    self.height = height;
+    self
+  }
+
+  pub fn scale(mut self, scale: f32) -> Self {
+    self.scale = scale;
+    self
+  }
+
+  pub fn font_dirs(mut self, font_dirs: Vec<PathBuf>) -> Self {
+    self.font_dirs = font_dirs;
+    self
+  }
+
+  pub fn system_fonts(mut self, system_fonts: bool) -> Self {
+    self.system_fonts = system_fonts;
+    self
+  }
+
+  pub fn validate(&self) -> Result<(), Error> {
+    let invalid = |message: String| Err(Error::new(ErrorKind::InvalidInput, message));
+
+    if self.width == Some(0) || self.height == Some(0) {
+      return invalid("SVG width and height must be above 0".to_string());
+    }
+    if !(self.scale > 0.0 && self.scale.is_finite()) {
+      return invalid(format!("SVG scale must be above 0, got {}", self.scale));
+    }
+    if let Some(dir) = self.font_dirs.iter().find(|dir| !dir.is_dir()) {
+      return invalid(format!("Font directory {} does not exist", dir.display()));
+    }
+    Ok(())
+  }
+
+  /// Pixel size for an SVG that declares itself `width` x `height`.
+  fn target_size(&self, width: f32, height: f32) -> (f32, f32) {
+    let scale = match (self.width, self.height) {
+      (Some(w), Some(h)) => (w as f32 / width).min(h as f32 / height),
+      (Some(w), None) => w as f32 / width,
+      (None, Some(h)) => h as f32 / height,
+      (None, None) => self.scale,
+    };
+    (width * scale, height * scale)
+  }
+}
+
+/// Rasterizes SVG files. Fonts are loaded once, when it is created.
+pub struct SvgRasterizer {
+  options: SvgOptions,
+  fonts: Arc<usvg::fontdb::Database>,
+}
+
+impl SvgRasterizer {
+  pub fn new(options: SvgOptions) -> Self {
+    let mut fonts = usvg::fontdb::Database::new();
+    for dir in &options.font_dirs {
+      // Unreadable font files are skipped silently, so an unusable directory shows up only here.
+      let loaded = fonts.len();
+      fonts.load_fonts_dir(dir);
+      if fonts.len() == loaded {
+        tracing::warn!(dir = %dir.display(), "no fonts found in font directory");
+      }
+    }
+    if options.system_fonts {
+      fonts.load_system_fonts();
+    }
+    tracing::debug!(fonts = fonts.len(), "loaded fonts for SVG text");
+
+    Self {
+      options,
+      fonts: Arc::new(fonts),
+    }
+  }
+
+  pub fn rasterize(&self, path: &Path) -> Result<DynamicImage, Error> {
+    let data = std::fs::read(path)?;
+    let options = usvg::Options {
+      // Images referenced by relative paths are resolved next to the SVG.
+      resources_dir: path.parent().map(Path::to_path_buf),
+      fontdb: self.fonts.clone(),
+      ..Default::default()
+    };
+
+    let tree = usvg::Tree::from_data(&data, &options)
+      .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Invalid SVG: {}", e)))?;
+    let size = tree.size();
+    let (width, height) = self.options.target_size(size.width(), size.height());
+    let (pixel_width, pixel_height) = (width.round().max(1.0), height.round().max(1.0));
+    if pixel_width > MAX_DIMENSION as f32 || pixel_height > MAX_DIMENSION as f32 {
+      return Err(Error::new(
+        ErrorKind::InvalidInput,
+        format!(
+          "SVG would be rasterized at {}x{}, more than {} pixels per side",
+          pixel_width, pixel_height, MAX_DIMENSION
+        ),
+      ));
+    }
+
+    let mut pixmap = Pixmap::new(pixel_width as u32, pixel_height as u32)
+      .ok_or_else(|| Error::other("Failed to allocate the SVG canvas"))?;
+    let transform = Transform::from_scale(pixel_width / size.width(), pixel_height / size.height());
+    resvg::render(&tree, transform, &mut pixmap.as_mut());
+
+    // tiny-skia stores premultiplied alpha.
+    let pixels = pixmap
+      .pixels()
+      .iter()
+      .flat_map(|pixel| {
+        let color = pixel.demultiply();
+        [color.red(), color.green(), color.blue(), color.alpha()]
+      })
+      .collect();
+    let image = RgbaImage::from_raw(pixmap.width(), pixmap.height(), pixels)
+      .ok_or_else(|| Error::other("SVG canvas has an unexpected size"))?;
+    Ok(DynamicImage::ImageRgba8(image))
+  }
+}
+
+impl Default for SvgRasterizer {
+  fn default() -> Self {
+    Self::new(SvgOptions::default())
+  }
+}
+
+/// Whether the file at `path` looks like an SVG document.
+pub fn is_svg_file(path: &Path) -> bool {
+  read_header(path).is_ok_and(|header| is_svg(path, &header))
+}
+
+/// Bytes read to recognize a file. SVG editors may put long comments before the root element.
+const HEADER_LEN: usize = 16 * 1024;
+
+/// The first bytes of a file, enough to recognize any supported format.
+pub fn read_header(path: &Path) -> Result<Vec<u8>, Error> {
+  let mut header = Vec::with_capacity(HEADER_LEN);
+  File::open(path)?
+    .take(HEADER_LEN as u64)
+    .read_to_end(&mut header)?;
+  Ok(header)
+}
+
+/// Whether `header`, the start of a file named `path`, looks like an SVG document: its root
+/// element, after any XML declaration, comments and DOCTYPE, is `<svg>`.
+pub fn is_svg(path: &Path, header: &[u8]) -> bool {
+  if header.starts_with(&[0x1f, 0x8b]) {
+    return path
+      .extension()
+      .is_some_and(|ext| ext.eq_ignore_ascii_case("svgz"));
+  }
+
+  let text = String::from_utf8_lossy(header);
+  let mut rest = text.trim_start_matches('\u{feff}');
+  loop {
+    rest = rest.trim_start();
+    let end = if rest.starts_with("<?") {
+      rest.find("?>").map(|i| i + 2)
+    } else if rest.starts_with("<!--") {
+      rest.find("-->").map(|i| i + 3)
+    } else if rest.starts_with("<!") {
+      // A DOCTYPE, which may declare entities between brackets.
+      let close = match (rest.find('['), rest.find('>')) {
+        (Some(open), Some(close)) if open < close => rest[open..].find(']').map(|i| open + i),
+        (_, close) => close,
+      };
+      close.and_then(|close| rest[close..].find('>').map(|i| close + i + 1))
+    } else {
+      break;
+    };
+    match end {
+      Some(end) => rest = &rest[end..],
+      // The prolog runs past the header.
+      None => return false,
+    }
+  }
+
+  let Some(tag) = rest.strip_prefix('<') else {
+    return false;
+  };
+  let name = tag
+    .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+    .next()
+    .unwrap_or_default();
+  // The root may be namespace prefixed, as in `<svg:svg>`.
+  name.rsplit(':').next() == Some("svg")
+}
+
+#[cfg(test)]
+mod tests {
+  use image::GenericImageView;
+  use tempfile::tempdir;
+
+  use super::*;
+
+  const ICON: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" width="24" height="12">
+    <rect width="12" height="12" fill="#ff0000"/>
+  </svg>"##;
+
+  fn rasterize(options: SvgOptions) -> DynamicImage {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("icon.svg");
+    std::fs::write(&path, ICON).unwrap();
+    SvgRasterizer::new(options.system_fonts(false))
+      .rasterize(&path)
+      .unwrap()
+  }
+
+  #[test]
+  fn test_rasterize_at_declared_size_with_alpha() {
+    let image = rasterize(SvgOptions::new());
+
+    assert_eq!(image.dimensions(), (24, 12));
+    assert_eq!(image.get_pixel(2, 2).0, [255, 0, 0, 255]);
+    assert_eq!(image.get_pixel(20, 2).0[3], 0);
+  }
+
+  #[test]
+  fn test_rasterize_at_requested_size() {
+    assert_eq!(
+      rasterize(SvgOptions::new().scale(2.0)).dimensions(),
+      (48, 24)
+    );
+    assert_eq!(
+      rasterize(SvgOptions::new().width(Some(96))).dimensions(),
+      (96, 48)
+    );
+    assert_eq!(
+      rasterize(SvgOptions::new().height(Some(36))).dimensions(),
+      (72, 36)
+    );
+    assert_eq!(
+      rasterize(SvgOptions::new().width(Some(48)).height(Some(48))).dimensions(),
+      (48, 24)
+    );
+  }
+
+  #[test]
+  fn test_is_svg() {
+    assert!(is_svg(Path::new("icon.svg"), ICON.as_bytes()));
+    assert!(is_svg(
+      Path::new("icon"),
+      b"<?xml version=\"1.0\"?>\n<svg xmlns"
+    ));
+    assert!(is_svg(Path::new("icon.svgz"), &[0x1f, 0x8b, 8]));
+    assert!(!is_svg(Path::new("notes.gz"), &[0x1f, 0x8b, 8]));
+    assert!(!is_svg(Path::new("notes.txt"), b"hello"));
+
+    let prolog = format!(
+      "\u{feff}<?xml version=\"1.0\"?>\n<!-- {} -->\n<!DOCTYPE svg PUBLIC \"-//W3C//DTD SVG 1.1//EN\" \
+       [<!ENTITY ns \"http://www.w3.org/2000/svg\">]>\n<svg:svg xmlns:svg=\"&ns;\"/>",
+      "x".repeat(2000)
+    );
+    assert!(is_svg(Path::new("icon"), prolog.as_bytes()));
+    assert!(!is_svg(
+      Path::new("page.html"),
+      b"<!DOCTYPE html>\n<html><body><svg></svg></body></html>"
+    ));
+    assert!(!is_svg(
+      Path::new("notes.txt"),
+      b"Draw it with <svg> and <rect>."
+    ));
+    assert!(!is_svg(Path::new("icon"), b"<svgz/>"));
+  }
+
+  #[test]
+  fn test_validate() {
+    assert!(SvgOptions::new().validate().is_ok());
+    assert!(SvgOptions::new().scale(0.0).validate().is_err());
+    assert!(SvgOptions::new().width(Some(0)).validate().is_err());
+    assert!(SvgOptions::new()
+      .font_dirs(vec![PathBuf::from("/does/not/exist")])
+      .validate()
+      .is_err());
+  }
+}
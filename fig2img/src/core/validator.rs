@@ -0,0 +1,200 @@
+use std::fmt;
+
+use image::{DynamicImage, GenericImageView, ImageFormat};
+
+/// Allowed difference, in pixels, between the rendered and the expected size. Figma rounds
+/// fractional bounds when rendering.
+const DIMENSION_TOLERANCE: u32 = 1;
+
+#[derive(Debug, PartialEq)]
+pub enum ValidationError {
+  Empty,
+  UnexpectedContentType(String),
+  UnrecognizedFormat,
+  UnexpectedFormat(ImageFormat),
+  Undecodable(String),
+}
+
+impl fmt::Display for ValidationError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Empty => write!(f, "empty response body"),
+      Self::UnexpectedContentType(content_type) => {
+        write!(f, "unexpected content type: {}", content_type)
+      }
+      Self::UnrecognizedFormat => write!(f, "response is not a recognised image"),
+      Self::UnexpectedFormat(format) => write!(f, "expected a PNG image, got {:?}", format),
+      Self::Undecodable(reason) => write!(f, "image could not be decoded: {}", reason),
+    }
+  }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// A downloaded image that decoded successfully, with anything that looks off about it.
+#[derive(Debug)]
+pub struct ValidatedImage {
+  pub width: u32,
+  pub height: u32,
+  pub warnings: Vec<String>,
+}
+
+/// Checks that `bytes` is a decodable PNG, using the magic bytes rather than the URL or the
+/// file name. `expected_size` is the node's size in pixels at the requested scale.
+pub fn validate_png(
+  bytes: &[u8],
+  content_type: Option<&str>,
+  expected_size: Option<(u32, u32)>,
+) -> Result<ValidatedImage, ValidationError> {
+  if bytes.is_empty() {
+    return Err(ValidationError::Empty);
+  }
+
+  if let Some(content_type) = content_type {
+    let is_image = content_type
+      .trim()
+      .to_ascii_lowercase()
+      .starts_with("image/");
+    let is_binary = content_type.starts_with("application/octet-stream");
+    if !is_image && !is_binary {
+      return Err(ValidationError::UnexpectedContentType(
+        content_type.to_string(),
+      ));
+    }
+  }
+
+  match image::guess_format(bytes) {
+    Ok(ImageFormat::Png) => {}
+    Ok(format) => return Err(ValidationError::UnexpectedFormat(format)),
+    Err(_) => return Err(ValidationError::UnrecognizedFormat),
+  }
+
+  let image = image::load_from_memory_with_format(bytes, ImageFormat::Png)
+    .map_err(|e| ValidationError::Undecodable(e.to_string()))?;
+  let (width, height) = image.dimensions();
+
+  let mut warnings = Vec::new();
+
+  if let Some((expected_width, expected_height)) = expected_size {
+    if width.abs_diff(expected_width) > DIMENSION_TOLERANCE
+      || height.abs_diff(expected_height) > DIMENSION_TOLERANCE
+    {
+      warnings.push(format!(
+        "rendered at {}x{}, expected {}x{}",
+        width, height, expected_width, expected_height
+      ));
+    }
+  }
+
+  warnings.extend(blank_render_warning(&image));
+
+  Ok(ValidatedImage {
+    width,
+    height,
+    warnings,
+  })
+}
+
+fn blank_render_warning(image: &DynamicImage) -> Option<String> {
+  let rgba = image.to_rgba8();
+  let mut pixels = rgba.pixels();
+  let first = *pixels.next()?;
+
+  if first[3] == 0 && rgba.pixels().all(|p| p[3] == 0) {
+    return Some("render is fully transparent".to_string());
+  }
+
+  if pixels.all(|p| *p == first) {
+    return Some("render is a single solid color".to_string());
+  }
+
+  None
+}
+
+/// Pixel size of a node whose bounds are `width` x `height` points, rendered at `scale`.
+pub fn expected_size(bounds: Option<(f64, f64)>, scale: f32) -> Option<(u32, u32)> {
+  let (width, height) = bounds?;
+  let scale = f64::from(scale);
+
+  Some((
+    (width * scale).round() as u32,
+    (height * scale).round() as u32,
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+
+  use image::{Rgba, RgbaImage};
+
+  use super::*;
+
+  fn encode_png(image: RgbaImage) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    image
+      .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+      .unwrap();
+    bytes
+  }
+
+  fn gradient(width: u32, height: u32) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, y| Rgba([x as u8, y as u8, 0, 255]))
+  }
+
+  #[test]
+  fn test_validate_png() {
+    let bytes = encode_png(gradient(20, 10));
+    let validated = validate_png(&bytes, Some("image/png"), Some((20, 10))).unwrap();
+
+    assert_eq!((validated.width, validated.height), (20, 10));
+    assert!(validated.warnings.is_empty());
+  }
+
+  #[test]
+  fn test_validate_png_rejects_invalid_content() {
+    assert_eq!(
+      validate_png(&[], Some("image/png"), None).unwrap_err(),
+      ValidationError::Empty
+    );
+    assert_eq!(
+      validate_png(b"<html></html>", Some("text/html"), None).unwrap_err(),
+      ValidationError::UnexpectedContentType("text/html".to_string())
+    );
+    assert_eq!(
+      validate_png(b"<html></html>", None, None).unwrap_err(),
+      ValidationError::UnrecognizedFormat
+    );
+
+    let mut truncated = encode_png(gradient(20, 10));
+    truncated.truncate(40);
+    assert!(matches!(
+      validate_png(&truncated, Some("image/png"), None),
+      Err(ValidationError::Undecodable(_))
+    ));
+  }
+
+  #[test]
+  fn test_validate_png_flags_suspicious_renders() {
+    let bytes = encode_png(gradient(20, 10));
+    let validated = validate_png(&bytes, None, Some((40, 20))).unwrap();
+    assert_eq!(
+      validated.warnings,
+      vec!["rendered at 20x10, expected 40x20"]
+    );
+
+    let transparent = encode_png(RgbaImage::new(4, 4));
+    let validated = validate_png(&transparent, None, None).unwrap();
+    assert_eq!(validated.warnings, vec!["render is fully transparent"]);
+
+    let solid = encode_png(RgbaImage::from_pixel(4, 4, Rgba([255, 255, 255, 255])));
+    let validated = validate_png(&solid, None, None).unwrap();
+    assert_eq!(validated.warnings, vec!["render is a single solid color"]);
+  }
+
+  #[test]
+  fn test_expected_size() {
+    assert_eq!(expected_size(Some((100.4, 50.0)), 2.0), Some((201, 100)));
+    assert_eq!(expected_size(None, 2.0), None);
+  }
+}
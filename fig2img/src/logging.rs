@@ -0,0 +1,103 @@
+use std::fs::OpenOptions;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+use crate::progress_bars::ProgressBars;
+
+/// Maps `--quiet` and the number of `-v` flags to the most verbose level that is logged.
+pub fn level(verbose: u8, quiet: bool) -> LevelFilter {
+  match (quiet, verbose) {
+    (true, _) => LevelFilter::ERROR,
+    (false, 0) => LevelFilter::WARN,
+    (false, 1) => LevelFilter::INFO,
+    (false, 2) => LevelFilter::DEBUG,
+    (false, _) => LevelFilter::TRACE,
+  }
+}
+
+/// Installs the global subscriber. Logs go to stderr, or to `log_file` when one is given.
+/// Closing spans are logged with their timings, so `-v` shows how long each API call, download
+/// and encode took.
+pub fn init(
+  level: LevelFilter,
+  log_file: Option<&Path>,
+  progress_bars: Option<Arc<ProgressBars>>,
+) -> io::Result<()> {
+  // Dependencies only get to report warnings; their debug output is noise for our users.
+  let targets = Targets::new()
+    .with_target(env!("CARGO_CRATE_NAME"), level)
+    .with_default(level.min(LevelFilter::WARN));
+
+  let layer = tracing_subscriber::fmt::layer().with_span_events(FmtSpan::CLOSE);
+
+  match log_file {
+    Some(path) => {
+      let file = OpenOptions::new().create(true).append(true).open(path)?;
+      let layer = layer.with_ansi(false).with_writer(Mutex::new(file));
+      tracing_subscriber::registry()
+        .with(targets)
+        .with(layer)
+        .init();
+    }
+    None => {
+      let layer = layer
+        .with_ansi(io::stderr().is_terminal())
+        .with_target(false)
+        .with_writer(StderrWriter { progress_bars });
+      tracing_subscriber::registry()
+        .with(targets)
+        .with(layer)
+        .init();
+    }
+  }
+
+  Ok(())
+}
+
+/// Writes to stderr, suspending the progress bars around each log line.
+struct StderrWriter {
+  progress_bars: Option<Arc<ProgressBars>>,
+}
+
+impl Write for &StderrWriter {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    match &self.progress_bars {
+      Some(bars) => bars.suspend(|| io::stderr().write(buf)),
+      None => io::stderr().write(buf),
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    io::stderr().flush()
+  }
+}
+
+impl<'a> MakeWriter<'a> for StderrWriter {
+  type Writer = &'a StderrWriter;
+
+  fn make_writer(&'a self) -> Self::Writer {
+    self
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_level() {
+    assert_eq!(level(0, false), LevelFilter::WARN);
+    assert_eq!(level(1, false), LevelFilter::INFO);
+    assert_eq!(level(2, false), LevelFilter::DEBUG);
+    assert_eq!(level(5, false), LevelFilter::TRACE);
+    assert_eq!(level(2, true), LevelFilter::ERROR);
+  }
+}
@@ -1,25 +1,135 @@
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::sync::Arc;
+
 use clap::Parser;
 
 mod cli;
+mod logging;
+mod progress_bars;
 
 use cli::{Cli, Commands};
+use fig2img::commands::convert::ConvertOptions;
 use fig2img::commands::download::DownloadOptions;
+use fig2img::commands::output::{JsonOutput, OutputFormat};
+use fig2img::commands::progress::Progress;
 use fig2img::commands::{self};
+use fig2img::config;
+use fig2img::core::converter::{CommandConverter, ConverterRegistry, EncoderOptions};
 
 #[tokio::main]
 async fn main() {
   let cli = Cli::parse();
 
-  match cli.command {
-    Commands::Download { output } => {
-      commands::download::execute(output, DownloadOptions::default()).await;
+  let json_output =
+    (cli.output_format != OutputFormat::Text).then(|| Arc::new(JsonOutput::new(cli.output_format)));
+
+  // Bars replace the per-file lines, and only make sense on an interactive terminal.
+  let progress_bars = (json_output.is_none() && !cli.quiet && std::io::stdout().is_terminal())
+    .then(|| Arc::new(progress_bars::ProgressBars::new()));
+  let progress = match (&json_output, &progress_bars) {
+    (Some(json), _) => Progress::new(json.clone()),
+    (None, Some(bars)) => Progress::new(bars.clone()),
+    (None, None) => Progress::default(),
+  };
+  let quiet = cli.quiet || json_output.is_some() || progress_bars.is_some();
+
+  let level = logging::level(cli.verbose, cli.quiet);
+  if let Err(e) = logging::init(level, cli.log_file.as_deref(), progress_bars.clone()) {
+    eprintln!("[❌] Failed to open log file: {}", e);
+    std::process::exit(1);
+  }
+
+  let report = match cli.command {
+    Commands::Download {
+      output,
+      content_hash,
+      manifest,
+      scale,
+      derive_scales,
+      sharpen,
+      connect_timeout,
+      read_timeout,
+      api_timeout,
+      retries,
+      jobs,
+      max_per_host,
+    } => {
+      let options = DownloadOptions::new()
+        .content_hash(content_hash)
+        .manifest(manifest)
+        .scale(scale)
+        .derive_scales(derive_scales)
+        .sharpen(sharpen)
+        .connect_timeout(connect_timeout)
+        .read_timeout(read_timeout)
+        .api_timeout(api_timeout)
+        .retries(retries)
+        .jobs(jobs)
+        .max_per_host(max_per_host)
+        .quiet(quiet)
+        .progress(progress);
+      commands::download::execute(output, options).await
     }
     Commands::Convert {
       input,
       output,
       format,
+      content_hash,
+      manifest,
+      inputs,
+      encoder,
+      resize,
+      svg,
+      jobs,
+      options,
     } => {
-      commands::convert::execute(input, output, format).await;
+      let encoder = encoder.apply(config::encoder_options());
+      let threads = encoder
+        .threads_per_image()
+        .unwrap_or_else(|| commands::convert::threads_per_image(jobs));
+      let encoder = encoder.threads(Some(threads));
+      let options = ConvertOptions::new()
+        .content_hash(content_hash)
+        .manifest(manifest)
+        .encoder(encoder)
+        .inputs(inputs.into())
+        .resize(resize.into())
+        .svg(svg.into())
+        .jobs(jobs)
+        .converters(converters(&encoder, options.into_iter().collect()))
+        .quiet(quiet)
+        .progress(progress);
+      commands::convert::execute(input, output, format, options).await
+    }
+  };
+
+  if let Some(bars) = progress_bars {
+    bars.finish();
+  }
+
+  match json_output {
+    Some(json) => {
+      json.finish(&report);
+      report.print_failures();
     }
+    None if !cli.quiet || report.has_failures() => report.print_summary(),
+    None => {}
   }
+
+  if report.has_failures() {
+    std::process::exit(1);
+  }
+}
+
+/// The built-in converters plus the external command converters from the config file, which
+/// replace built-in ones with the same name.
+fn converters(encoder: &EncoderOptions, options: BTreeMap<String, String>) -> ConverterRegistry {
+  let mut converters = ConverterRegistry::with_defaults(encoder);
+
+  for (name, config) in config::command_converters() {
+    converters.register(CommandConverter::new(name, config).with_options(&options));
+  }
+
+  converters
 }
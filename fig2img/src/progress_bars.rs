@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use fig2img::commands::progress::{Phase, ProgressEvent, ProgressListener};
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
+
+const BAR_TEMPLATE: &str =
+  "{prefix:>10.bold} [{bar:30.cyan/blue}] {pos}/{len} {per_sec} ETA {eta} {msg}";
+const SPINNER_TEMPLATE: &str = "{prefix:>10.bold} {spinner} {elapsed} {msg}";
+
+struct PhaseBar {
+  bar: ProgressBar,
+  bytes: u64,
+}
+
+/// Terminal progress bars: one for the whole run and one per phase.
+pub struct ProgressBars {
+  multi: MultiProgress,
+  overall: ProgressBar,
+  phases: Mutex<HashMap<Phase, PhaseBar>>,
+}
+
+impl ProgressBars {
+  pub fn new() -> Self {
+    let multi = MultiProgress::new();
+    let overall = multi.add(ProgressBar::new(0));
+    overall.set_style(Self::bar_style());
+    overall.set_prefix("Overall");
+
+    Self {
+      multi,
+      overall,
+      phases: Mutex::new(HashMap::new()),
+    }
+  }
+
+  pub fn finish(&self) {
+    self.overall.finish_and_clear();
+    let _ = self.multi.clear();
+  }
+
+  /// Hides the bars while `f` writes to the terminal, so log lines don't tear through them.
+  pub fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+    self.multi.suspend(f)
+  }
+
+  fn bar_style() -> ProgressStyle {
+    ProgressStyle::with_template(BAR_TEMPLATE)
+      .unwrap()
+      .progress_chars("=> ")
+  }
+
+  fn spinner_style() -> ProgressStyle {
+    ProgressStyle::with_template(SPINNER_TEMPLATE).unwrap()
+  }
+
+  fn start_phase(&self, phase: Phase, total: Option<u64>) -> PhaseBar {
+    let bar = match phase {
+      // A single request to Figma; the count only tells how many nodes it covers.
+      Phase::FetchFile | Phase::Render => {
+        let bar = ProgressBar::new_spinner().with_style(Self::spinner_style());
+        bar.enable_steady_tick(Duration::from_millis(100));
+        if let Some(total) = total {
+          bar.set_message(format!("{} nodes", total));
+        }
+        bar
+      }
+      Phase::Download | Phase::Convert => {
+        let total = total.unwrap_or_default();
+        self.overall.inc_length(total);
+        ProgressBar::new(total).with_style(Self::bar_style())
+      }
+    };
+
+    bar.set_prefix(phase.to_string());
+    PhaseBar {
+      bar: self.multi.insert_before(&self.overall, bar),
+      bytes: 0,
+    }
+  }
+
+  fn advance(&self, phase_bar: &mut PhaseBar, bytes: u64) {
+    phase_bar.bytes += bytes;
+    phase_bar.bar.inc(1);
+    self.overall.inc(1);
+
+    let elapsed = phase_bar.bar.elapsed().as_secs_f64().max(f64::EPSILON);
+    phase_bar.bar.set_message(format!(
+      "{} ({}/s)",
+      HumanBytes(phase_bar.bytes),
+      HumanBytes((phase_bar.bytes as f64 / elapsed) as u64)
+    ));
+  }
+}
+
+impl ProgressListener for ProgressBars {
+  fn on_event(&self, event: &ProgressEvent) {
+    let mut phases = self.phases.lock().unwrap();
+
+    match event {
+      ProgressEvent::PhaseStarted { phase, total } => {
+        let phase_bar = self.start_phase(*phase, *total);
+        phases.insert(*phase, phase_bar);
+      }
+      ProgressEvent::TaskStarted { .. } => {}
+      ProgressEvent::TaskCompleted { phase, bytes, .. } => {
+        if let Some(phase_bar) = phases.get_mut(phase) {
+          self.advance(phase_bar, *bytes);
+        }
+      }
+      ProgressEvent::TaskSkipped { phase, .. } | ProgressEvent::TaskFailed { phase, .. } => {
+        if let Some(phase_bar) = phases.get_mut(phase) {
+          self.advance(phase_bar, 0);
+        }
+      }
+      ProgressEvent::PhaseFinished { phase } => {
+        if let Some(phase_bar) = phases.get(phase) {
+          phase_bar.bar.finish();
+        }
+      }
+    }
+  }
+}
@@ -1,7 +1,24 @@
+use std::path::{Path, PathBuf};
+
 pub fn sanitize(name: &str) -> String {
   name.replace(['/', '\\', ':', '*', '?', '"', '<', '>', '|'], "_")
 }
 
+pub fn with_content_hash(path: &Path, hash: &str) -> PathBuf {
+  let stem = path
+    .file_stem()
+    .and_then(|s| s.to_str())
+    .unwrap_or_default();
+  let short_hash = &hash[..hash.len().min(8)];
+
+  let file_name = match path.extension().and_then(|e| e.to_str()) {
+    Some(ext) => format!("{}.{}.{}", stem, short_hash, ext),
+    None => format!("{}.{}", stem, short_hash),
+  };
+
+  path.with_file_name(file_name)
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -12,4 +29,16 @@ mod tests {
     assert_eq!(sanitize("test:file*"), "test_file_");
     assert_eq!(sanitize("normal.png"), "normal.png");
   }
+
+  #[test]
+  fn test_with_content_hash() {
+    assert_eq!(
+      with_content_hash(Path::new("out/hero.webp"), "3f2a9c1b77e0d4aa"),
+      PathBuf::from("out/hero.3f2a9c1b.webp")
+    );
+    assert_eq!(
+      with_content_hash(Path::new("hero"), "3f2a9c1b"),
+      PathBuf::from("hero.3f2a9c1b")
+    );
+  }
 }
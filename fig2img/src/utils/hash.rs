@@ -0,0 +1,149 @@
+use std::collections::BTreeMap;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+use tokio::fs;
+
+use crate::utils::filename;
+
+pub const HASH_MANIFEST_FILENAME: &str = "hash-manifest.json";
+
+pub fn content_hash(bytes: &[u8]) -> String {
+  format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Renames `path` to its content-addressed name, e.g. `hero.webp` -> `hero.3f2a9c1b.webp`.
+pub async fn rename_with_content_hash(path: &Path) -> Result<PathBuf, Error> {
+  let bytes = fs::read(path).await?;
+  let hashed_path = filename::with_content_hash(path, &content_hash(&bytes));
+
+  fs::rename(path, &hashed_path).await?;
+  Ok(hashed_path)
+}
+
+/// Maps logical file names to their content-addressed names.
+#[derive(Default)]
+pub struct HashManifest {
+  entries: BTreeMap<String, String>,
+}
+
+impl HashManifest {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn from_entries(entries: &[(PathBuf, PathBuf)]) -> Self {
+    let mut manifest = Self::new();
+    for (logical_path, hashed_path) in entries {
+      manifest.insert(logical_path, hashed_path);
+    }
+    manifest
+  }
+
+  /// Like `from_entries`, but keyed by paths relative to `dir`, so files with the same name in
+  /// different subdirectories don't collide.
+  pub fn from_entries_in(dir: &Path, entries: &[(PathBuf, PathBuf)]) -> Self {
+    let mut manifest = Self::new();
+    for (logical_path, hashed_path) in entries {
+      if let (Some(logical), Some(hashed)) = (
+        relative_name(dir, logical_path),
+        relative_name(dir, hashed_path),
+      ) {
+        manifest.entries.insert(logical, hashed);
+      }
+    }
+    manifest
+  }
+
+  pub fn insert(&mut self, logical_path: &Path, hashed_path: &Path) {
+    if let (Some(logical), Some(hashed)) = (file_name(logical_path), file_name(hashed_path)) {
+      self.entries.insert(logical, hashed);
+    }
+  }
+
+  pub async fn write(&self, dir: &Path) -> Result<PathBuf, Error> {
+    let path = dir.join(HASH_MANIFEST_FILENAME);
+    let json = serde_json::to_vec_pretty(&self.entries)?;
+
+    fs::write(&path, json).await?;
+    Ok(path)
+  }
+}
+
+fn file_name(path: &Path) -> Option<String> {
+  path.file_name()?.to_str().map(str::to_string)
+}
+
+/// `path` relative to `dir` with `/` separators, or its file name when it is elsewhere.
+pub fn relative_name(dir: &Path, path: &Path) -> Option<String> {
+  let Ok(relative) = path.strip_prefix(dir) else {
+    return file_name(path);
+  };
+  let parts = relative
+    .components()
+    .map(|component| component.as_os_str().to_str())
+    .collect::<Option<Vec<_>>>()?;
+  Some(parts.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+  use tempfile::tempdir;
+
+  use super::*;
+
+  #[test]
+  fn test_content_hash() {
+    assert_eq!(
+      content_hash(b"fig2img"),
+      format!("{:x}", Sha256::digest(b"fig2img"))
+    );
+    assert_eq!(content_hash(b"").len(), 64);
+  }
+
+  #[tokio::test]
+  async fn test_rename_with_content_hash() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().join("hero.webp");
+    std::fs::write(&path, b"image bytes").unwrap();
+
+    let hashed_path = rename_with_content_hash(&path).await.unwrap();
+    let expected = format!("hero.{}.webp", &content_hash(b"image bytes")[..8]);
+
+    assert_eq!(hashed_path, temp_dir.path().join(expected));
+    assert!(hashed_path.exists());
+    assert!(!path.exists());
+  }
+
+  #[tokio::test]
+  async fn test_hash_manifest_write() {
+    let temp_dir = tempdir().unwrap();
+    let mut manifest = HashManifest::new();
+    manifest.insert(
+      Path::new("out/hero.webp"),
+      Path::new("out/hero.3f2a9c1b.webp"),
+    );
+
+    let path = manifest.write(temp_dir.path()).await.unwrap();
+    let written: BTreeMap<String, String> =
+      serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap();
+
+    assert_eq!(written["hero.webp"], "hero.3f2a9c1b.webp");
+  }
+
+  #[test]
+  fn test_hash_manifest_keys_subdirectories() {
+    let dir = Path::new("out");
+    let manifest = HashManifest::from_entries_in(
+      dir,
+      &[
+        (dir.join("hero.webp"), dir.join("hero.aa.webp")),
+        (dir.join("icons/hero.webp"), dir.join("icons/hero.bb.webp")),
+      ],
+    );
+
+    assert_eq!(manifest.entries["hero.webp"], "hero.aa.webp");
+    assert_eq!(manifest.entries["icons/hero.webp"], "icons/hero.bb.webp");
+  }
+}
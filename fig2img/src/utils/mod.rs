@@ -1 +1,3 @@
 pub mod filename;
+pub mod hash;
+pub mod redact;
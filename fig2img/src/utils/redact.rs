@@ -0,0 +1,41 @@
+pub const REDACTED: &str = "[redacted]";
+
+/// Strips the query string and fragment from `url` before it is logged. Render URLs can be
+/// pre-signed, in which case the query carries the credentials.
+pub fn url(url: &str) -> String {
+  match url.find(['?', '#']) {
+    Some(index) => format!("{}?{}", &url[..index], REDACTED),
+    None => url.to_string(),
+  }
+}
+
+/// `url` without its query string and fragment, for files that outlive the run, where even the
+/// redaction marker is noise.
+pub fn url_without_query(url: &str) -> &str {
+  url.split(['?', '#']).next().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_url() {
+    assert_eq!(
+      url("https://s3.amazonaws.com/images/a.png?X-Amz-Signature=secret"),
+      "https://s3.amazonaws.com/images/a.png?[redacted]"
+    );
+    assert_eq!(
+      url("https://api.figma.com/v1/files/key"),
+      "https://api.figma.com/v1/files/key"
+    );
+  }
+
+  #[test]
+  fn test_url_without_query() {
+    assert_eq!(
+      url_without_query("https://s3.amazonaws.com/images/a.png?X-Amz-Signature=secret#top"),
+      "https://s3.amazonaws.com/images/a.png"
+    );
+  }
+}